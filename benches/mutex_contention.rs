@@ -0,0 +1,98 @@
+// Compares the locks under contention: every thread increments a shared
+// counter, and we measure how long it takes until all of them are done.
+//
+//     cargo bench --bench mutex_contention
+
+use crust_of_rust::pointers::{parking::ParkingMutex, unlock::Mutex};
+use std::{
+    sync, thread,
+    time::{Duration, Instant},
+};
+
+const ITERATIONS: usize = 100_000;
+const RUNS: usize = 5;
+
+trait Lock: Sync {
+    const NAME: &'static str;
+    fn new() -> Self;
+    fn increment(&self);
+    fn get(&self) -> usize;
+}
+
+impl Lock for Mutex<usize> {
+    fn new() -> Self {
+        Mutex::new(0)
+    }
+    const NAME: &'static str = "unlock::Mutex";
+    fn increment(&self) {
+        self.with_lock(|v| *v += 1);
+    }
+    fn get(&self) -> usize {
+        self.with_lock(|v| *v)
+    }
+}
+
+impl Lock for ParkingMutex<usize> {
+    fn new() -> Self {
+        ParkingMutex::new(0)
+    }
+    const NAME: &'static str = "parking::ParkingMutex";
+    fn increment(&self) {
+        self.with_lock(|v| *v += 1);
+    }
+    fn get(&self) -> usize {
+        *self.lock()
+    }
+}
+
+impl Lock for sync::Mutex<usize> {
+    fn new() -> Self {
+        sync::Mutex::new(0)
+    }
+    const NAME: &'static str = "std::sync::Mutex";
+    fn increment(&self) {
+        *self.lock().unwrap() += 1;
+    }
+    fn get(&self) -> usize {
+        *self.lock().unwrap()
+    }
+}
+
+fn run<L: Lock>(threads: usize) -> Duration {
+    let lock = L::new();
+    let start = Instant::now();
+    thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|| {
+                for _ in 0..ITERATIONS {
+                    L::increment(&lock);
+                }
+            });
+        }
+    });
+    let elapsed = start.elapsed();
+    assert_eq!(lock.get(), threads * ITERATIONS);
+    elapsed
+}
+
+fn bench<L: Lock>(threads: usize) {
+    // Take the best run, the others mostly measure noise from the rest of the system.
+    let best = (0..RUNS).map(|_| run::<L>(threads)).min().unwrap();
+    println!(
+        "{:>22} {:>2} threads: {:>8.2?} ({:.1} ns/lock)",
+        L::NAME,
+        threads,
+        best,
+        best.as_nanos() as f64 / (threads * ITERATIONS) as f64
+    );
+}
+
+fn main() {
+    let cpus = thread::available_parallelism().map_or(4, |n| n.get());
+    for threads in [1, 2, cpus, cpus * 2] {
+        bench::<Mutex<usize>>(threads);
+        bench::<ParkingMutex<usize>>(threads);
+        bench::<sync::Mutex<usize>>(threads);
+        println!();
+    }
+}
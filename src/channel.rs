@@ -1,7 +1,8 @@
-use std::{
-    collections::VecDeque,
-    sync::{Arc, Condvar, Mutex},
-};
+#[cfg(feature = "vec-deque")]
+use crate::pointers::vec_deque::VecDeque;
+use crate::sync::{Arc, Condvar, Mutex};
+#[cfg(not(feature = "vec-deque"))]
+use std::collections::VecDeque;
 
 pub struct Sender<T> {
     shared: Arc<Shared<T>>,
@@ -131,3 +132,38 @@ mod tests {
         tx.send(2);
     }
 }
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::*;
+    use crate::sync::thread;
+
+    #[test]
+    fn loom_send_then_disconnect() {
+        loom::model(|| {
+            let (mut tx, mut rx) = channel();
+            let t = thread::spawn(move || {
+                tx.send(1);
+                drop(tx);
+            });
+            // The value is never lost to the disconnect, and `recv` never
+            // sleeps through the last sender going away.
+            assert_eq!(rx.recv(), Some(1));
+            assert_eq!(rx.recv(), None);
+            t.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn loom_senders_disconnect() {
+        loom::model(|| {
+            let (tx1, mut rx) = channel::<()>();
+            let tx2 = tx1.clone();
+            let t1 = thread::spawn(move || drop(tx1));
+            let t2 = thread::spawn(move || drop(tx2));
+            assert_eq!(rx.recv(), None);
+            t1.join().unwrap();
+            t2.join().unwrap();
+        });
+    }
+}
@@ -1,4 +1,7 @@
-use std::fmt::Debug;
+use std::borrow::{Borrow, BorrowMut};
+use std::cmp::Ordering;
+use std::fmt::{self, Debug};
+use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
 use std::ptr::NonNull;
@@ -27,7 +30,7 @@ impl<T> Drop for Boks<T> {
 
         // Safety: p was constructed from a Box in the first place, and has not been freed,
         // otherwise sinse self still exits (otherwise, drop could not be called).
-        unsafe { Box::from_raw(self.p.as_mut()) };
+        unsafe { drop(Box::from_raw(self.p.as_ptr())) };
     }
 }
 
@@ -38,7 +41,7 @@ impl<T> Deref for Boks<T> {
         // Safety: is valid since it was constructed from a valid T,
         // and turned into a pointer through Box which creates aligned pointers,
         // and hasn't been freed, since self is alived.
-        unsafe { &*self.p.as_ref() }
+        unsafe { self.p.as_ref() }
     }
 }
 
@@ -48,7 +51,93 @@ impl<T> DerefMut for Boks<T> {
         // and turned into a pointer through Box which creates aligned pointers,
         // and hasn't been freed, since self is alived.
         // Also, since we have &mut self, no other mutable reference has been given out to p.
-        unsafe { &mut *self.p.as_mut() }
+        unsafe { self.p.as_mut() }
+    }
+}
+
+impl<T: Debug> Debug for Boks<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Boks<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl<T> fmt::Pointer for Boks<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Pointer::fmt(&self.p, f)
+    }
+}
+
+impl<T: Clone> Clone for Boks<T> {
+    fn clone(&self) -> Self {
+        Boks::new((**self).clone())
+    }
+}
+
+impl<T: PartialEq> PartialEq for Boks<T> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: Eq> Eq for Boks<T> {}
+
+impl<T: PartialOrd> PartialOrd for Boks<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        (**self).partial_cmp(&**other)
+    }
+}
+
+impl<T: Ord> Ord for Boks<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (**self).cmp(&**other)
+    }
+}
+
+impl<T: Hash> Hash for Boks<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+impl<T: Default> Default for Boks<T> {
+    fn default() -> Self {
+        Boks::new(T::default())
+    }
+}
+
+impl<T> From<T> for Boks<T> {
+    fn from(t: T) -> Self {
+        Boks::new(t)
+    }
+}
+
+impl<T> AsRef<T> for Boks<T> {
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+impl<T> AsMut<T> for Boks<T> {
+    fn as_mut(&mut self) -> &mut T {
+        self
+    }
+}
+
+impl<T> Borrow<T> for Boks<T> {
+    fn borrow(&self) -> &T {
+        self
+    }
+}
+
+impl<T> BorrowMut<T> for Boks<T> {
+    fn borrow_mut(&mut self) -> &mut T {
+        self
     }
 }
 
@@ -98,3 +187,24 @@ fn gogo() {
     // let bk2: Boks<&'static str> = Boks::new("eee");
     // bk1 = bk2;
 }
+
+#[test]
+fn boks_as_map_key() {
+    use std::collections::{BTreeMap, HashMap};
+
+    let mut map = HashMap::new();
+    map.insert(Boks::new(String::from("a")), 1);
+    map.insert(Boks::from(String::from("b")), 2);
+    assert_eq!(map[&Boks::new(String::from("a"))], 1);
+    // `Borrow<T>` lets us look up by the pointee, like with `Box`.
+    assert_eq!(map.get(&String::from("b")), Some(&2));
+
+    let mut map = BTreeMap::new();
+    map.insert(Boks::new(2), "two");
+    map.insert(Boks::new(1), "one");
+    assert_eq!(map.keys().map(|k| **k).collect::<Vec<_>>(), [1, 2]);
+    assert_eq!(map.get(&1), Some(&"one"));
+
+    assert_eq!(format!("{:?} {}", Boks::new("x"), Boks::new(3)), "\"x\" 3");
+    assert_eq!(Boks::<i32>::default(), Boks::new(0));
+}
@@ -1,9 +1,8 @@
+use crate::sync::{thread, Arc, Mutex};
 use std::{
     future::Future,
     pin::Pin,
-    sync::{Arc, Mutex},
     task::{Context, Poll, Waker},
-    thread,
     time::Duration,
 };
 
@@ -71,6 +70,7 @@ mod tests {
         executor.run();
     }
 
+    #[cfg(not(loom))]
     #[tokio::test]
     async fn async_move() {
         let my_string = "foo".to_string();
@@ -7,5 +7,6 @@ pub mod pointers;
 pub mod sort;
 pub mod str_split;
 pub mod strtok;
+mod sync;
 pub mod trait_obj;
 pub mod vec_macro;
@@ -0,0 +1,401 @@
+use super::cell::Cell;
+use std::{
+    alloc::{self, Layout},
+    error::Error,
+    fmt, ptr,
+    ptr::NonNull,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// What `RawVec` needs from an allocator, a stable take on
+/// `std::alloc::Allocator`.
+///
+/// # Safety
+///
+/// Memory returned by `allocate`, `grow` and `shrink` must stay valid until
+/// it's passed to `deallocate`, `grow` or `shrink`, and may not be handed out
+/// again before that. A copy or reference of the allocator must be able to free
+/// what the original allocated.
+pub unsafe trait Allocator {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError>;
+
+    /// # Safety
+    ///
+    /// `ptr` must have been allocated by this allocator with `layout`.
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout);
+
+    /// # Safety
+    ///
+    /// `ptr` must have been allocated by this allocator with `old`, and `new`
+    /// must be at least as large. On success `ptr` may no longer be used, on
+    /// failure it's still valid.
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old: Layout,
+        new: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        let new_ptr = self.allocate(new)?;
+        ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr(), old.size());
+        self.deallocate(ptr, old);
+        Ok(new_ptr)
+    }
+
+    /// # Safety
+    ///
+    /// Like `grow`, but `new` must be at most as large as `old`.
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old: Layout,
+        new: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        let new_ptr = self.allocate(new)?;
+        ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr(), new.size());
+        self.deallocate(ptr, old);
+        Ok(new_ptr)
+    }
+}
+
+unsafe impl<A: Allocator + ?Sized> Allocator for &A {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        (**self).allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        (**self).deallocate(ptr, layout)
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old: Layout,
+        new: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        (**self).grow(ptr, old, new)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old: Layout,
+        new: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        (**self).shrink(ptr, old, new)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AllocError;
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("memory allocation failed")
+    }
+}
+
+impl Error for AllocError {}
+
+// A dangling but well aligned pointer for zero-sized allocations, which the
+// global allocator doesn't take.
+fn dangling(layout: Layout) -> NonNull<u8> {
+    // SAFETY: Alignments are never zero.
+    unsafe { NonNull::new_unchecked(layout.align() as *mut u8) }
+}
+
+// The global allocator, which is whatever `#[global_allocator]` says.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Global;
+
+unsafe impl Allocator for Global {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        if layout.size() == 0 {
+            return Ok(dangling(layout));
+        }
+        // SAFETY: The size isn't zero.
+        NonNull::new(unsafe { alloc::alloc(layout) }).ok_or(AllocError)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() != 0 {
+            alloc::dealloc(ptr.as_ptr(), layout);
+        }
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old: Layout,
+        new: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        // `realloc` can only keep the alignment, and not start from nothing.
+        if old.size() == 0 || old.align() != new.align() {
+            let new_ptr = self.allocate(new)?;
+            ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr(), old.size());
+            self.deallocate(ptr, old);
+            return Ok(new_ptr);
+        }
+        NonNull::new(alloc::realloc(ptr.as_ptr(), old, new.size())).ok_or(AllocError)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old: Layout,
+        new: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        if new.size() == 0 || old.align() != new.align() {
+            let new_ptr = self.allocate(new)?;
+            ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr(), new.size());
+            self.deallocate(ptr, old);
+            return Ok(new_ptr);
+        }
+        NonNull::new(alloc::realloc(ptr.as_ptr(), old, new.size())).ok_or(AllocError)
+    }
+}
+
+// Hands out memory from one fixed chunk by bumping an offset, and frees it all
+// at once when dropped. Freeing or resizing the most recent allocation is done
+// in place, anything else just leaves a hole.
+pub struct Bump {
+    chunk: NonNull<u8>,
+    layout: Layout,
+    // Offset of the first free byte, and of the most recent allocation.
+    next: Cell<usize>,
+    last: Cell<usize>,
+}
+
+impl Bump {
+    // The chunk is aligned for anything up to 16 bytes, larger alignments
+    // waste some of it.
+    pub fn with_capacity(bytes: usize) -> Self {
+        let layout = Layout::from_size_align(bytes, 16).expect("capacity overflow");
+        let chunk = Global
+            .allocate(layout)
+            .unwrap_or_else(|_| alloc::handle_alloc_error(layout));
+        Self {
+            chunk,
+            layout,
+            next: Cell::new(0),
+            last: Cell::new(0),
+        }
+    }
+
+    // Bytes handed out so far, including holes and padding.
+    pub fn allocated(&self) -> usize {
+        self.next.get()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.layout.size()
+    }
+
+    fn offset(&self, ptr: NonNull<u8>) -> usize {
+        ptr.as_ptr() as usize - self.chunk.as_ptr() as usize
+    }
+}
+
+unsafe impl Allocator for Bump {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        let base = self.chunk.as_ptr() as usize;
+        let unaligned = base.checked_add(self.next.get()).ok_or(AllocError)?;
+        // Round up to the alignment, which is always a power of two.
+        let start = unaligned
+            .checked_add(layout.align() - 1)
+            .ok_or(AllocError)?
+            & !(layout.align() - 1);
+        let end = start.checked_add(layout.size()).ok_or(AllocError)?;
+        if end > base + self.layout.size() {
+            return Err(AllocError);
+        }
+        self.last.set(start - base);
+        self.next.set(end - base);
+        // SAFETY: `start` is within the chunk, which isn't null.
+        Ok(unsafe { NonNull::new_unchecked(self.chunk.as_ptr().add(start - base)) })
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, _: Layout) {
+        // Only the most recent allocation can be given back.
+        if self.offset(ptr) == self.last.get() {
+            self.next.set(self.last.get());
+        }
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old: Layout,
+        new: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        let offset = self.offset(ptr);
+        let fits = offset
+            .checked_add(new.size())
+            .is_some_and(|end| end <= self.layout.size());
+        if offset == self.last.get() && (ptr.as_ptr() as usize).is_multiple_of(new.align()) && fits
+        {
+            self.next.set(offset + new.size());
+            return Ok(ptr);
+        }
+        let new_ptr = self.allocate(new)?;
+        ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr(), old.size());
+        Ok(new_ptr)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old: Layout,
+        new: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        if !(ptr.as_ptr() as usize).is_multiple_of(new.align()) {
+            let new_ptr = self.allocate(new)?;
+            ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr(), new.size());
+            self.deallocate(ptr, old);
+            return Ok(new_ptr);
+        }
+        let offset = self.offset(ptr);
+        if offset == self.last.get() {
+            self.next.set(offset + new.size());
+        }
+        Ok(ptr)
+    }
+}
+
+impl Drop for Bump {
+    fn drop(&mut self) {
+        // SAFETY: Allocated in `with_capacity`. Whatever still points into the
+        // chunk borrows `self`, so it's gone by now.
+        unsafe { Global.deallocate(self.chunk, self.layout) }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AllocCounts {
+    pub allocations: usize,
+    pub deallocations: usize,
+    pub grows: usize,
+    pub shrinks: usize,
+}
+
+// Wraps another allocator and counts the calls made to it.
+#[derive(Debug, Default)]
+pub struct Counting<A = Global> {
+    inner: A,
+    allocations: AtomicUsize,
+    deallocations: AtomicUsize,
+    grows: AtomicUsize,
+    shrinks: AtomicUsize,
+}
+
+impl Counting {
+    pub fn new() -> Self {
+        Self::wrap(Global)
+    }
+}
+
+impl<A> Counting<A> {
+    pub fn wrap(inner: A) -> Self {
+        Self {
+            inner,
+            allocations: AtomicUsize::new(0),
+            deallocations: AtomicUsize::new(0),
+            grows: AtomicUsize::new(0),
+            shrinks: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn counts(&self) -> AllocCounts {
+        AllocCounts {
+            allocations: self.allocations.load(Ordering::Relaxed),
+            deallocations: self.deallocations.load(Ordering::Relaxed),
+            grows: self.grows.load(Ordering::Relaxed),
+            shrinks: self.shrinks.load(Ordering::Relaxed),
+        }
+    }
+}
+
+unsafe impl<A: Allocator> Allocator for Counting<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.allocations.fetch_add(1, Ordering::Relaxed);
+        self.inner.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.deallocations.fetch_add(1, Ordering::Relaxed);
+        self.inner.deallocate(ptr, layout)
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old: Layout,
+        new: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        self.grows.fetch_add(1, Ordering::Relaxed);
+        self.inner.grow(ptr, old, new)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old: Layout,
+        new: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        self.shrinks.fetch_add(1, Ordering::Relaxed);
+        self.inner.shrink(ptr, old, new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bump() {
+        let bump = Bump::with_capacity(64);
+        let a = bump.allocate(Layout::new::<u8>()).unwrap();
+        let b = bump.allocate(Layout::new::<u64>()).unwrap();
+        assert_eq!(b.as_ptr() as usize % 8, 0);
+        assert_eq!(bump.allocated(), 16);
+
+        unsafe {
+            // The last allocation grows in place, others move.
+            let b2 = bump
+                .grow(b, Layout::new::<u64>(), Layout::new::<[u64; 2]>())
+                .unwrap();
+            assert_eq!((b2, bump.allocated()), (b, 24));
+            let a2 = bump
+                .grow(a, Layout::new::<u8>(), Layout::new::<[u8; 2]>())
+                .unwrap();
+            assert_ne!(a2, a);
+            // Giving back the last one makes room again.
+            bump.deallocate(a2, Layout::new::<[u8; 2]>());
+            assert_eq!(bump.allocated(), 24);
+        }
+        assert_eq!(bump.allocate(Layout::new::<[u8; 41]>()), Err(AllocError));
+        assert!(bump.allocate(Layout::new::<[u8; 40]>()).is_ok());
+    }
+
+    #[test]
+    fn counting() {
+        let counting = Counting::new();
+        let layout = Layout::new::<u32>();
+        unsafe {
+            let p = counting.allocate(layout).unwrap();
+            let p = counting.grow(p, layout, Layout::new::<[u32; 4]>()).unwrap();
+            let p = counting
+                .shrink(p, Layout::new::<[u32; 4]>(), Layout::new::<[u32; 2]>())
+                .unwrap();
+            counting.deallocate(p, Layout::new::<[u32; 2]>());
+        }
+        assert_eq!(
+            counting.counts(),
+            AllocCounts {
+                allocations: 1,
+                deallocations: 1,
+                grows: 1,
+                shrinks: 1,
+            }
+        );
+    }
+}
@@ -1,50 +1,287 @@
+use super::set_data_ptr;
+use crate::sync::{
+    atomic::{self, AtomicUsize, Ordering},
+    hint,
+};
 use std::{
+    alloc::{self, Layout},
+    borrow::Borrow,
+    cmp, fmt,
+    hash::{Hash, Hasher},
     marker::PhantomData,
+    mem::{self, ManuallyDrop},
     ops::Deref,
-    ptr::NonNull,
-    sync::atomic::{self, AtomicUsize, Ordering},
+    ptr::{self, NonNull},
 };
 
-pub struct Arc<T> {
+// A soft limit on the amount of references that may be made to an `Arc`,
+// going above it would risk overflowing the counts.
+const MAX_REFCOUNT: usize = isize::MAX as usize;
+
+pub struct Arc<T: ?Sized> {
     ptr: NonNull<ArcInner<T>>,
     phantom: PhantomData<ArcInner<T>>,
 }
 
-pub struct ArcInner<T> {
-    rc: AtomicUsize,
-    data: T,
+// `repr(C)` so the offset of `data` only depends on its own layout, see `rc::RcInner`.
+#[repr(C)]
+pub struct ArcInner<T: ?Sized> {
+    strong: AtomicUsize,
+    // All strong pointers together hold one weak reference, like `rc::Rc`.
+    // `usize::MAX` means the count is "locked" by `Arc::is_unique`.
+    weak: AtomicUsize,
+    // `data` is dropped by the last `Arc`, the block is freed by the last `Weak`.
+    // It has to be the last field since it may be unsized.
+    data: ManuallyDrop<T>,
 }
 
 impl<T> Arc<T> {
     pub fn new(data: T) -> Self {
         let inner = Box::new(ArcInner {
-            rc: AtomicUsize::new(1),
-            data,
+            strong: AtomicUsize::new(1),
+            weak: AtomicUsize::new(1),
+            data: ManuallyDrop::new(data),
         });
         Self {
             ptr: unsafe { NonNull::new_unchecked(Box::into_raw(inner)) },
             phantom: PhantomData,
         }
     }
+
+    pub fn try_unwrap(this: Self) -> Result<T, Self> {
+        // Relaxed is enough for the CAS itself, the fence below takes care of
+        // the synchronization with other `Arc`s, just like in `Drop`.
+        if this
+            .inner()
+            .strong
+            .compare_exchange(1, 0, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+        {
+            return Err(this);
+        }
+        atomic::fence(Ordering::Acquire);
+
+        let this = ManuallyDrop::new(this);
+        // SAFETY: We took the last strong reference, so nobody else can touch
+        // `data` anymore, and `strong` being zero means `Weak::upgrade` fails.
+        let data = unsafe { ptr::read(&*this.inner().data) };
+        // Release the implicit weak reference held by the strong pointers.
+        drop(Weak { ptr: this.ptr });
+        Ok(data)
+    }
+
+    pub fn into_inner(this: Self) -> Option<T> {
+        let this = ManuallyDrop::new(this);
+        // Same as `Drop`: the last `Arc` gets the data out instead of dropping it.
+        // Unlike `try_unwrap(this).ok()` this never drops the data when two
+        // threads race to unwrap the last two clones.
+        if this.inner().strong.fetch_sub(1, Ordering::Release) != 1 {
+            return None;
+        }
+        atomic::fence(Ordering::Acquire);
+
+        // SAFETY: See `try_unwrap`.
+        let data = unsafe { ptr::read(&*this.inner().data) };
+        drop(Weak { ptr: this.ptr });
+        Some(data)
+    }
+}
+
+impl<T: ?Sized> Arc<T> {
+    // Allocates a block for a value with the layout and metadata of `ptr`, with
+    // both counts set to one. The value itself is left uninitialized.
+    //
+    // SAFETY: `ptr` must point to a valid value, it's only used for its layout.
+    unsafe fn allocate_for_ptr(ptr: *const T) -> NonNull<ArcInner<T>> {
+        let (layout, _) = Layout::new::<ArcInner<()>>()
+            .extend(Layout::for_value(&*ptr))
+            .unwrap();
+        let layout = layout.pad_to_align();
+        let mem = alloc::alloc(layout);
+        if mem.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+
+        let inner = set_data_ptr(ptr as *mut ArcInner<T>, mem);
+        ptr::write(&mut (*inner).strong, AtomicUsize::new(1));
+        ptr::write(&mut (*inner).weak, AtomicUsize::new(1));
+        NonNull::new_unchecked(inner)
+    }
+
+    // Moves the value behind `ptr` into a new block, the caller is responsible
+    // for freeing the memory of `ptr` without dropping the value.
+    unsafe fn copy_from_ptr(ptr: *const T) -> Self {
+        let inner = Self::allocate_for_ptr(ptr);
+        let size = mem::size_of_val(&*ptr);
+        ptr::copy_nonoverlapping(
+            ptr as *const u8,
+            &mut (*inner.as_ptr()).data as *mut ManuallyDrop<T> as *mut u8,
+            size,
+        );
+        Arc {
+            ptr: inner,
+            phantom: PhantomData,
+        }
+    }
+
+    pub fn into_raw(this: Self) -> *const T {
+        let this = ManuallyDrop::new(this);
+        &**this as *const T
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must come from `Arc::into_raw` on an `Arc<U>`, where `U` is either `T`
+    /// or a type that unsizes to `T` (e.g. `U: Trait` and `T = dyn Trait`).
+    pub unsafe fn from_raw(ptr: *const T) -> Self {
+        // Find the block from the data, see `rc::Rc::from_raw`.
+        let (_, offset) = Layout::new::<ArcInner<()>>()
+            .extend(Layout::for_value(&*ptr))
+            .unwrap();
+        let inner = (ptr as *mut ArcInner<T>).wrapping_byte_sub(offset);
+        Arc {
+            ptr: NonNull::new_unchecked(inner),
+            phantom: PhantomData,
+        }
+    }
+
+    fn inner(&self) -> &ArcInner<T> {
+        // SAFETY: The block is alive as long as there is a strong pointer to it.
+        unsafe { self.ptr.as_ref() }
+    }
+
+    pub fn downgrade(this: &Self) -> Weak<T> {
+        let mut cur = this.inner().weak.load(Ordering::Relaxed);
+        loop {
+            // The weak count is locked by `is_unique`, spin until it's released.
+            if cur == usize::MAX {
+                hint::spin_loop();
+                cur = this.inner().weak.load(Ordering::Relaxed);
+                continue;
+            }
+            if cur > MAX_REFCOUNT {
+                std::process::abort();
+            }
+            // Acquire synchronizes with the Release store in `is_unique`, so
+            // the uniqueness check there can't miss this new weak pointer.
+            match this.inner().weak.compare_exchange_weak(
+                cur,
+                cur + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Weak { ptr: this.ptr },
+                Err(old) => cur = old,
+            }
+        }
+    }
+
+    pub fn strong_count(this: &Self) -> usize {
+        this.inner().strong.load(Ordering::Relaxed)
+    }
+
+    pub fn weak_count(this: &Self) -> usize {
+        let count = this.inner().weak.load(Ordering::Relaxed);
+        // If the weak count is locked, the count was 1 (only the implicit weak
+        // held by the strong pointers) when it was locked.
+        if count == usize::MAX {
+            0
+        } else {
+            count - 1
+        }
+    }
+
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        // Only compare addresses, see `rc::Rc::ptr_eq`.
+        this.ptr.as_ptr() as *const () == other.ptr.as_ptr() as *const ()
+    }
+
+    // Whether this is the only `Arc` and there are no `Weak`s, in which case
+    // it's fine to hand out `&mut T`.
+    fn is_unique(&mut self) -> bool {
+        // Lock the weak count if we're the only weak pointer (the implicit one),
+        // so no `Weak` can be created by `downgrade` while we check `strong`.
+        // Acquire synchronizes with the Release decrement in `Weak::drop`, so
+        // any access through a now gone `Weak` happens before our check.
+        if self
+            .inner()
+            .weak
+            .compare_exchange(1, usize::MAX, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            // Acquire synchronizes with the Release decrement in `Arc::drop`,
+            // so all accesses through other now gone `Arc`s happen before ours.
+            let unique = self.inner().strong.load(Ordering::Acquire) == 1;
+            // Release synchronizes with the Acquire in `downgrade`.
+            self.inner().weak.store(1, Ordering::Release);
+            unique
+        } else {
+            false
+        }
+    }
+
+    pub fn get_mut(this: &mut Self) -> Option<&mut T> {
+        if this.is_unique() {
+            // SAFETY: We're the only pointer to the block, and we have `&mut self`.
+            Some(unsafe { &mut (*this.ptr.as_ptr()).data })
+        } else {
+            None
+        }
+    }
 }
 
-unsafe impl<T: Sync + Send> Send for Arc<T> {}
-unsafe impl<T: Sync + Send> Sync for Arc<T> {}
+impl<T: Clone> Arc<T> {
+    pub fn make_mut(this: &mut Self) -> &mut T {
+        // Acquire synchronizes with the Release decrements in `Drop` of other
+        // `Arc`s, so their writes happen before we take the data over.
+        if this
+            .inner()
+            .strong
+            .compare_exchange(1, 0, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            // Other `Arc`s exist, clone the data into a fresh block.
+            *this = Arc::new((**this).clone());
+        } else if this.inner().weak.load(Ordering::Relaxed) != 1 {
+            // We're the last `Arc` but there are `Weak`s. `strong` is zero now,
+            // so they can't upgrade anymore: move the data into a fresh block
+            // and leave the old one to the `Weak`s.
+            let old = Weak { ptr: this.ptr };
+            // SAFETY: `strong` is zero, so nobody else can read `data` anymore.
+            let data = unsafe { ptr::read(&*old.inner().data) };
+            let fresh = Arc::new(data);
+            // SAFETY: We took over the strong reference of `this`, so it must
+            // not be dropped. `old` releases its implicit weak reference.
+            unsafe { ptr::write(this, fresh) };
+        } else {
+            // We were the only reference of any kind, restore the strong count.
+            // Release pairs with the Acquire in `Weak::upgrade`, though no
+            // `Weak` exists right now.
+            this.inner().strong.store(1, Ordering::Release);
+        }
+        // SAFETY: Either `this` is a fresh block or we've checked it's unique.
+        unsafe { &mut (*this.ptr.as_ptr()).data }
+    }
+}
 
-impl<T> Deref for Arc<T> {
+unsafe impl<T: ?Sized + Sync + Send> Send for Arc<T> {}
+unsafe impl<T: ?Sized + Sync + Send> Sync for Arc<T> {}
+
+impl<T: ?Sized> Deref for Arc<T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
-        let inner = unsafe { self.ptr.as_ref() };
-        &inner.data
+        &self.inner().data
     }
 }
 
-impl<T> Clone for Arc<T> {
+impl<T: ?Sized> Clone for Arc<T> {
     fn clone(&self) -> Self {
-        let inner = unsafe { self.ptr.as_ref() };
-        let old_rc = inner.rc.fetch_add(1, Ordering::Relaxed);
-        if old_rc >= isize::MAX as usize {
+        // Relaxed is fine here: a new reference can only be created from an
+        // existing one, and passing an existing reference to another thread
+        // already provides any required synchronization.
+        let old_rc = self.inner().strong.fetch_add(1, Ordering::Relaxed);
+        if old_rc >= MAX_REFCOUNT {
             std::process::abort();
         }
         Self {
@@ -54,19 +291,502 @@ impl<T> Clone for Arc<T> {
     }
 }
 
-impl<T> Drop for Arc<T> {
+impl<T: ?Sized> Drop for Arc<T> {
     fn drop(&mut self) {
-        let inner = unsafe { self.ptr.as_ref() };
-        if inner.rc.fetch_sub(1, Ordering::Release) != 1 {
+        if self.inner().strong.fetch_sub(1, Ordering::Release) != 1 {
             return;
         }
         // This fence is needed to prevent reordering of the use and deletion
         // of the data.
         atomic::fence(Ordering::Acquire);
         // This is safe as we know we have the last pointer to the `ArcInner`
-        // and that its pointer is valid.
+        // and that its pointer is valid, and `strong` is zero now so `Weak`s
+        // can't upgrade anymore.
+        unsafe {
+            ManuallyDrop::drop(&mut (*self.ptr.as_ptr()).data);
+        }
+        // Release the implicit weak reference held by the strong pointers.
+        drop(Weak { ptr: self.ptr });
+    }
+}
+
+impl<T: ?Sized> From<Box<T>> for Arc<T> {
+    fn from(b: Box<T>) -> Self {
+        let ptr = Box::into_raw(b);
         unsafe {
-            Box::from_raw(self.ptr.as_ptr());
+            let arc = Arc::copy_from_ptr(ptr);
+            // Free the Box allocation without dropping the value we moved out.
+            drop(Box::from_raw(ptr as *mut ManuallyDrop<T>));
+            arc
         }
     }
 }
+
+impl<T> From<Vec<T>> for Arc<[T]> {
+    fn from(mut v: Vec<T>) -> Self {
+        unsafe {
+            let arc = Arc::copy_from_ptr(&*v as *const [T]);
+            // The elements were moved into the block, so the Vec only frees
+            // its buffer.
+            v.set_len(0);
+            arc
+        }
+    }
+}
+
+impl<T: Clone> From<&[T]> for Arc<[T]> {
+    fn from(slice: &[T]) -> Self {
+        Arc::from(slice.to_vec())
+    }
+}
+
+impl From<&str> for Arc<str> {
+    fn from(s: &str) -> Self {
+        let arc = Arc::<[u8]>::from(s.as_bytes());
+        // SAFETY: `str` has the same layout as `[u8]`, and the bytes are UTF-8.
+        unsafe { Arc::from_raw(Arc::into_raw(arc) as *const str) }
+    }
+}
+
+impl From<String> for Arc<str> {
+    fn from(s: String) -> Self {
+        Arc::from(&*s)
+    }
+}
+
+/// Turns an `Arc<T>` into an `Arc<U>` where `T` unsizes to `U`, see `unsize_rc!`.
+#[macro_export]
+macro_rules! unsize_arc {
+    ($arc:expr, $ty:ty) => {{
+        let ptr = $crate::pointers::arc::Arc::into_raw($arc);
+        let ptr: *const $ty = ptr;
+        let arc: $crate::pointers::arc::Arc<$ty> =
+            unsafe { $crate::pointers::arc::Arc::from_raw(ptr) };
+        arc
+    }};
+}
+
+pub struct Weak<T: ?Sized> {
+    ptr: NonNull<ArcInner<T>>,
+}
+
+unsafe impl<T: ?Sized + Sync + Send> Send for Weak<T> {}
+unsafe impl<T: ?Sized + Sync + Send> Sync for Weak<T> {}
+
+impl<T: ?Sized> Weak<T> {
+    fn inner(&self) -> &ArcInner<T> {
+        // SAFETY: The block is kept alive by our weak reference, although
+        // `data` may already have been dropped.
+        unsafe { self.ptr.as_ref() }
+    }
+
+    pub fn upgrade(&self) -> Option<Arc<T>> {
+        // We can't use `fetch_add` like `Arc::clone` does, the count must never
+        // go from zero back to one.
+        let mut n = self.inner().strong.load(Ordering::Relaxed);
+        loop {
+            if n == 0 {
+                return None;
+            }
+            if n > MAX_REFCOUNT {
+                std::process::abort();
+            }
+            // Acquire on success synchronizes with the Release store of `strong`
+            // in `make_mut`, failure may be Relaxed since we don't touch `data`.
+            match self.inner().strong.compare_exchange_weak(
+                n,
+                n + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    return Some(Arc {
+                        ptr: self.ptr,
+                        phantom: PhantomData,
+                    })
+                }
+                Err(old) => n = old,
+            }
+        }
+    }
+
+    pub fn strong_count(&self) -> usize {
+        self.inner().strong.load(Ordering::Relaxed)
+    }
+
+    // Like std, 0 once the value is gone, even if other `Weak`s remain.
+    pub fn weak_count(&self) -> usize {
+        // `strong` first: while it's not 0, the strong pointers still hold
+        // their weak reference, which we don't count.
+        if self.inner().strong.load(Ordering::Relaxed) == 0 {
+            return 0;
+        }
+        // If the last strong pointer is dropped since, this is off by one,
+        // but the count is only a snapshot anyway. Can't underflow, we're a
+        // weak reference ourselves.
+        self.inner().weak.load(Ordering::Relaxed) - 1
+    }
+
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        self.ptr.as_ptr() as *const () == other.ptr.as_ptr() as *const ()
+    }
+}
+
+impl<T: ?Sized> Clone for Weak<T> {
+    fn clone(&self) -> Self {
+        // Relaxed is fine for the same reason as in `Arc::clone`. The count
+        // can't be locked by `is_unique` since we are a weak pointer ourselves.
+        let old = self.inner().weak.fetch_add(1, Ordering::Relaxed);
+        if old > MAX_REFCOUNT {
+            std::process::abort();
+        }
+        Weak { ptr: self.ptr }
+    }
+}
+
+impl<T: ?Sized> Drop for Weak<T> {
+    fn drop(&mut self) {
+        // Same as `Arc::drop`: Release so our uses of the block happen before
+        // the deallocation, and the fence to pair with the other Releases.
+        if self.inner().weak.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+        atomic::fence(Ordering::Acquire);
+        // SAFETY: We held the last reference of any kind. `data` was already
+        // dropped (or moved out), and `ManuallyDrop` keeps Box from dropping it.
+        unsafe { drop(Box::from_raw(self.ptr.as_ptr())) };
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Arc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display> fmt::Display for Arc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized> fmt::Pointer for Arc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Pointer::fmt(&(&**self as *const T), f)
+    }
+}
+
+impl<T: ?Sized + PartialEq> PartialEq for Arc<T> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: ?Sized + Eq> Eq for Arc<T> {}
+
+impl<T: ?Sized + PartialOrd> PartialOrd for Arc<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        (**self).partial_cmp(&**other)
+    }
+}
+
+impl<T: ?Sized + Ord> Ord for Arc<T> {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        (**self).cmp(&**other)
+    }
+}
+
+impl<T: ?Sized + Hash> Hash for Arc<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+impl<T: Default> Default for Arc<T> {
+    fn default() -> Self {
+        Arc::new(T::default())
+    }
+}
+
+impl<T> From<T> for Arc<T> {
+    fn from(t: T) -> Self {
+        Arc::new(t)
+    }
+}
+
+impl<T: ?Sized> AsRef<T> for Arc<T> {
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+impl<T: ?Sized> Borrow<T> for Arc<T> {
+    fn borrow(&self) -> &T {
+        self
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for Weak<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(Weak)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    struct DropCounter<'a> {
+        drops: &'a AtomicUsize,
+    }
+
+    impl Drop for DropCounter<'_> {
+        fn drop(&mut self) {
+            self.drops.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn counts() {
+        let a = Arc::new(1);
+        let b = a.clone();
+        let w = Arc::downgrade(&a);
+        assert_eq!(Arc::strong_count(&a), 2);
+        assert_eq!(Arc::weak_count(&a), 1);
+        assert!(Arc::ptr_eq(&a, &b));
+        assert!(!Arc::ptr_eq(&a, &Arc::new(1)));
+        drop(b);
+        assert_eq!(w.strong_count(), 1);
+        assert_eq!(w.weak_count(), 1);
+    }
+
+    #[test]
+    fn weak_upgrade() {
+        let drops = AtomicUsize::new(0);
+        let a = Arc::new(DropCounter { drops: &drops });
+        let w = Arc::downgrade(&a);
+        assert!(w.upgrade().is_some());
+        drop(a);
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+        assert!(w.upgrade().is_none());
+    }
+
+    #[test]
+    fn weak_outlives_clones() {
+        let drops = AtomicUsize::new(0);
+        let a = Arc::new(DropCounter { drops: &drops });
+        let weak1 = Arc::downgrade(&a);
+        let weak2 = weak1.clone();
+        assert_eq!(Arc::weak_count(&a), 2);
+        drop(weak1);
+        assert_eq!(weak2.weak_count(), 1);
+        let weak3 = weak2.clone();
+        drop(a);
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+        assert!(weak2.upgrade().is_none());
+        // No strong references left, so no weak ones are counted either.
+        assert_eq!((weak2.weak_count(), weak3.weak_count()), (0, 0));
+    }
+
+    #[test]
+    fn get_mut() {
+        let mut a = Arc::new(1);
+        *Arc::get_mut(&mut a).unwrap() = 2;
+        let b = a.clone();
+        assert!(Arc::get_mut(&mut a).is_none());
+        drop(b);
+        let w = Arc::downgrade(&a);
+        assert!(Arc::get_mut(&mut a).is_none());
+        drop(w);
+        assert_eq!(*Arc::get_mut(&mut a).unwrap(), 2);
+    }
+
+    #[test]
+    fn try_unwrap_and_into_inner() {
+        let a = Arc::new(String::from("hi"));
+        let b = a.clone();
+        let a = Arc::try_unwrap(a).unwrap_err();
+        assert_eq!(Arc::into_inner(b), None);
+        let w = Arc::downgrade(&a);
+        assert_eq!(Arc::try_unwrap(a).ok().unwrap(), "hi");
+        assert!(w.upgrade().is_none());
+
+        let a = Arc::new(String::from("hello"));
+        assert_eq!(Arc::into_inner(a).unwrap(), "hello");
+    }
+
+    #[test]
+    fn into_inner_races() {
+        for _ in 0..100 {
+            let a = Arc::new(vec![1, 2, 3]);
+            let b = a.clone();
+            let t = thread::spawn(move || Arc::into_inner(b));
+            let mine = Arc::into_inner(a);
+            let theirs = t.join().unwrap();
+            // Exactly one of the two gets the data.
+            assert!(mine.is_some() ^ theirs.is_some());
+        }
+    }
+
+    #[test]
+    fn make_mut() {
+        let mut a = Arc::new(1);
+        *Arc::make_mut(&mut a) += 1;
+        let b = a.clone();
+        // Shared, so it's cloned and `b` is untouched.
+        *Arc::make_mut(&mut a) += 1;
+        assert_eq!((*a, *b), (3, 2));
+        assert!(!Arc::ptr_eq(&a, &b));
+
+        // Unique but with a weak pointer, so the data is moved away from it.
+        let w = Arc::downgrade(&b);
+        let mut b = b;
+        *Arc::make_mut(&mut b) += 1;
+        assert_eq!(*b, 3);
+        assert!(w.upgrade().is_none());
+        assert_eq!(Arc::weak_count(&b), 0);
+    }
+
+    #[test]
+    fn upgrade_across_threads() {
+        let drops: &'static _ = Box::leak(Box::new(AtomicUsize::new(0)));
+        let a = Arc::new(DropCounter { drops });
+        let handles = (0..8)
+            .map(|_| {
+                let w = Arc::downgrade(&a);
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        if let Some(a) = w.upgrade() {
+                            drop(a.clone());
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        drop(a);
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn unsized_values() {
+        let arc: Arc<[String]> = Arc::from(vec![String::from("a"), String::from("b")]);
+        let other = arc.clone();
+        let handle = thread::spawn(move || other.concat());
+        assert_eq!(handle.join().unwrap(), "ab");
+        assert_eq!(Arc::strong_count(&arc), 1);
+
+        let arc: Arc<str> = Arc::from("hello");
+        assert_eq!(&*arc, "hello");
+
+        let drops = AtomicUsize::new(0);
+        let arc: Arc<dyn Send + Sync> =
+            Arc::from(Box::new(DropCounter { drops: &drops }) as Box<_>);
+        drop(arc);
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn unsize() {
+        let arc = Arc::new(vec![1, 2, 3]);
+        let weak = Arc::downgrade(&arc);
+        let dynamic: Arc<dyn Fn() -> usize + Send + Sync> =
+            crate::unsize_arc!(Arc::new(move || arc.len()), dyn Fn() -> usize + Send + Sync);
+        let handle = {
+            let dynamic = dynamic.clone();
+            thread::spawn(move || dynamic() * 2)
+        };
+        assert_eq!(handle.join().unwrap(), 6);
+        drop(dynamic);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn as_map_key() {
+        use std::collections::{BTreeMap, HashMap};
+
+        let mut map = HashMap::new();
+        map.insert(Arc::new(String::from("a")), 1);
+        map.insert(Arc::from(String::from("b")), 2);
+        // Equality and hashing go through the pointee, not the pointer.
+        assert_eq!(map[&Arc::new(String::from("a"))], 1);
+        assert_eq!(map.get(&String::from("b")), Some(&2));
+
+        let mut map = BTreeMap::new();
+        map.insert(Arc::<str>::from("b"), 2);
+        map.insert(Arc::<str>::from("a"), 1);
+        assert_eq!(map.keys().map(|k| &**k).collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(map.get("a"), Some(&1));
+    }
+
+    #[test]
+    fn formatting() {
+        let value = Arc::new(5);
+        assert_eq!(format!("{:?} {}", value, value), "5 5");
+        assert_eq!(format!("{:p}", value), format!("{:p}", &*value));
+        assert_eq!(format!("{:?}", Arc::downgrade(&value)), "(Weak)");
+        assert_eq!(Arc::<Vec<u8>>::default().len(), 0);
+        assert!(Arc::new(1) < Arc::new(2));
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::*;
+    use crate::sync::{thread, UnsafeCell};
+
+    // Written by one thread before it drops its `Arc`, and read in `Drop` by
+    // whichever thread drops the last one. Loom fails the test if the write
+    // doesn't happen before the read.
+    struct Value {
+        written: UnsafeCell<bool>,
+        drops: crate::sync::Arc<AtomicUsize>,
+    }
+
+    impl Drop for Value {
+        fn drop(&mut self) {
+            assert!(self.written.with(|v| unsafe { *v }));
+            self.drops.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn loom_drop() {
+        loom::model(|| {
+            let drops = crate::sync::Arc::new(AtomicUsize::new(0));
+            let a = Arc::new(Value {
+                written: UnsafeCell::new(false),
+                drops: drops.clone(),
+            });
+            let b = a.clone();
+            let t = thread::spawn(move || {
+                // SAFETY: Only this thread touches `written` until it drops `a`.
+                a.written.with_mut(|v| unsafe { *v = true });
+                drop(a);
+            });
+            drop(b);
+            t.join().unwrap();
+            assert_eq!(drops.load(Ordering::Relaxed), 1);
+        });
+    }
+
+    #[test]
+    fn loom_weak_upgrade() {
+        loom::model(|| {
+            let drops = crate::sync::Arc::new(AtomicUsize::new(0));
+            let a = Arc::new(Value {
+                written: UnsafeCell::new(true),
+                drops: drops.clone(),
+            });
+            let weak = Arc::downgrade(&a);
+            let t = thread::spawn(move || weak.upgrade().is_some());
+            drop(a);
+            // Whether or not the upgrade got in, the value is dropped once.
+            t.join().unwrap();
+            assert_eq!(drops.load(Ordering::Relaxed), 1);
+        });
+    }
+}
@@ -0,0 +1,258 @@
+use super::arc::Arc;
+use std::{
+    hint,
+    marker::PhantomData,
+    mem::ManuallyDrop,
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+};
+
+// An `Arc` that can be replaced while other threads read it.
+//
+// The hard part is `load`: reading the pointer and incrementing the strong
+// count of what it points to are two steps, and if a writer swaps the pointer
+// out and drops the last `Arc` in between, the reader increments a freed count.
+// So readers announce themselves in `readers` before reading the pointer, and a
+// writer only gives up the old `Arc` (which may be the last one) once no reader
+// that could have seen it is left. Readers never wait for anything, writers
+// wait for the readers that were in the middle of a `load` when they swapped.
+pub struct AtomicArc<T> {
+    ptr: AtomicPtr<T>,
+    readers: AtomicUsize,
+    // We own one strong reference to whatever `ptr` points to.
+    phantom: PhantomData<Arc<T>>,
+}
+
+unsafe impl<T: Send + Sync> Send for AtomicArc<T> {}
+unsafe impl<T: Send + Sync> Sync for AtomicArc<T> {}
+
+impl<T> AtomicArc<T> {
+    pub fn new(value: Arc<T>) -> Self {
+        Self {
+            ptr: AtomicPtr::new(Arc::into_raw(value) as *mut T),
+            readers: AtomicUsize::new(0),
+            phantom: PhantomData,
+        }
+    }
+
+    pub fn load(&self) -> Arc<T> {
+        // SeqCst here and in `wait_for_readers` makes sure that either the
+        // writer sees our increment, or we see the pointer it swapped in.
+        self.readers.fetch_add(1, Ordering::SeqCst);
+        let ptr = self.ptr.load(Ordering::SeqCst);
+        // SAFETY: The `Arc` behind `ptr` can't be dropped while we're counted
+        // in `readers`, so we can take a strong reference of our own.
+        let arc = unsafe {
+            let current = ManuallyDrop::new(Arc::from_raw(ptr));
+            Arc::clone(&current)
+        };
+        // Release so the increment of the strong count happens before a writer
+        // sees we're gone and drops its reference.
+        self.readers.fetch_sub(1, Ordering::Release);
+        arc
+    }
+
+    pub fn store(&self, value: Arc<T>) {
+        drop(self.swap(value));
+    }
+
+    pub fn swap(&self, value: Arc<T>) -> Arc<T> {
+        let new = Arc::into_raw(value) as *mut T;
+        let old = self.ptr.swap(new, Ordering::SeqCst);
+        self.wait_for_readers();
+        // SAFETY: We took over the reference that was owned by `self`.
+        unsafe { Arc::from_raw(old) }
+    }
+
+    // Stores `new` if the current value is `current` (by pointer). Like
+    // `AtomicPtr::compare_exchange`, returns the previous value on success, and
+    // the value that was there instead on failure, in which case `new` is
+    // dropped.
+    pub fn compare_exchange(&self, current: &Arc<T>, new: Arc<T>) -> Result<Arc<T>, Arc<T>> {
+        let current = &**current as *const T as *mut T;
+        let new = Arc::into_raw(new) as *mut T;
+        // Count ourselves as a reader, so if the exchange fails, what it saw
+        // stays alive until we've taken a reference to it, like in `load`.
+        self.readers.fetch_add(1, Ordering::SeqCst);
+        match self
+            .ptr
+            .compare_exchange(current, new, Ordering::SeqCst, Ordering::SeqCst)
+        {
+            Ok(old) => {
+                self.readers.fetch_sub(1, Ordering::Release);
+                self.wait_for_readers();
+                // SAFETY: We took over the reference that was owned by `self`.
+                Ok(unsafe { Arc::from_raw(old) })
+            }
+            Err(actual) => {
+                // SAFETY: See `load`.
+                let actual = unsafe {
+                    let actual = ManuallyDrop::new(Arc::from_raw(actual));
+                    Arc::clone(&actual)
+                };
+                self.readers.fetch_sub(1, Ordering::Release);
+                // SAFETY: `new` was never published, so we still own it.
+                drop(unsafe { Arc::from_raw(new) });
+                Err(actual)
+            }
+        }
+    }
+
+    // Like the old `AtomicPtr::compare_and_swap`: stores `new` if the current
+    // value is `current`, and returns the previous value either way. It was
+    // stored if that is `current`.
+    pub fn compare_and_swap(&self, current: &Arc<T>, new: Arc<T>) -> Arc<T> {
+        match self.compare_exchange(current, new) {
+            Ok(previous) | Err(previous) => previous,
+        }
+    }
+
+    // Waits until every `load` that may have read the pointer we just swapped
+    // out has incremented its strong count. Loads that start after the swap
+    // read the new pointer and don't matter, but they're counted all the same,
+    // so a writer can be held up by an endless stream of overlapping loads.
+    fn wait_for_readers(&self) {
+        // SeqCst for the reason in `load`, which is also Acquire to pair with
+        // the Release decrement there.
+        while self.readers.load(Ordering::SeqCst) != 0 {
+            hint::spin_loop();
+        }
+    }
+
+    pub fn into_inner(self) -> Arc<T> {
+        let this = ManuallyDrop::new(self);
+        // SAFETY: We own `self`, so there can't be any readers, and we take
+        // over its reference without running `Drop`.
+        unsafe { Arc::from_raw(this.ptr.load(Ordering::Relaxed)) }
+    }
+}
+
+impl<T> Drop for AtomicArc<T> {
+    fn drop(&mut self) {
+        // SAFETY: We have `&mut self`, so there are no readers left.
+        drop(unsafe { Arc::from_raw(*self.ptr.get_mut()) });
+    }
+}
+
+impl<T> From<Arc<T>> for AtomicArc<T> {
+    fn from(value: Arc<T>) -> Self {
+        Self::new(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn swap_and_compare() {
+        let config = AtomicArc::new(Arc::new(1));
+        assert_eq!(*config.load(), 1);
+        config.store(Arc::new(2));
+        assert_eq!(*config.swap(Arc::new(3)), 2);
+
+        let current = config.load();
+        let stale = Arc::new(3);
+        let new = Arc::new(4);
+        let actual = config.compare_exchange(&stale, new.clone()).err().unwrap();
+        // Fails even if the value is equal, and hands back what it found.
+        assert!(Arc::ptr_eq(&actual, &current));
+        assert_eq!(*config.load(), 3);
+        assert_eq!(Arc::strong_count(&new), 1);
+        drop(actual);
+
+        let previous = config.compare_exchange(&current, new.clone()).ok().unwrap();
+        assert!(Arc::ptr_eq(&previous, &current));
+        // `current` is no longer the value, so this fails now.
+        let actual = config
+            .compare_exchange(&current, Arc::new(5))
+            .err()
+            .unwrap();
+        assert!(Arc::ptr_eq(&actual, &new));
+        drop((previous, actual));
+        assert_eq!(Arc::strong_count(&current), 1);
+        assert_eq!(*config.into_inner(), 4);
+    }
+
+    #[test]
+    fn compare_and_swap() {
+        let first = Arc::new(1);
+        let config = AtomicArc::new(first.clone());
+        let previous = config.compare_and_swap(&first, Arc::new(2));
+        assert!(Arc::ptr_eq(&previous, &first));
+        assert_eq!(*config.load(), 2);
+
+        // `first` is gone, so nothing is stored, and we get what's there.
+        let new = Arc::new(3);
+        let previous = config.compare_and_swap(&first, new.clone());
+        assert_eq!(*previous, 2);
+        assert!(Arc::ptr_eq(&previous, &config.load()));
+        assert_eq!(Arc::strong_count(&new), 1);
+    }
+
+    #[test]
+    fn drops_old_values() {
+        let first = Arc::new(String::from("first"));
+        let config = AtomicArc::new(first.clone());
+        assert_eq!(Arc::strong_count(&first), 2);
+        let loaded = config.load();
+        config.store(Arc::new(String::from("second")));
+        assert_eq!(Arc::strong_count(&first), 2);
+        drop(loaded);
+        assert_eq!(Arc::strong_count(&first), 1);
+
+        let second = config.load();
+        drop(config);
+        assert_eq!(Arc::strong_count(&second), 1);
+    }
+
+    #[test]
+    fn hammer() {
+        struct Config {
+            version: usize,
+            // A check that no reader ever sees a freed or torn value.
+            check: Vec<usize>,
+        }
+
+        impl Config {
+            fn new(version: usize) -> Arc<Self> {
+                Arc::new(Config {
+                    version,
+                    check: vec![version; 16],
+                })
+            }
+        }
+
+        let config: &'static _ = Box::leak(Box::new(AtomicArc::new(Config::new(0))));
+        let readers = (0..6)
+            .map(|_| {
+                thread::spawn(move || {
+                    let mut last = 0;
+                    for _ in 0..20_000 {
+                        let current = config.load();
+                        assert!(current.check.iter().all(|&v| v == current.version));
+                        // A single writer only moves forward.
+                        assert!(current.version >= last);
+                        last = current.version;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        let writer = thread::spawn(move || {
+            for version in 1..=5_000 {
+                if version % 2 == 0 {
+                    config.store(Config::new(version));
+                } else {
+                    let current = config.load();
+                    let _ = config.compare_exchange(&current, Config::new(version));
+                }
+            }
+        });
+
+        writer.join().unwrap();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert_eq!(config.load().version, 5_000);
+    }
+}
@@ -0,0 +1,197 @@
+use super::parking::{wait, wake_all, ParkingMutex};
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU32, AtomicUsize, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+// Lets `n` threads (or tasks) wait for each other, and then go on together.
+// It can be used again right away for the next round.
+pub struct Barrier {
+    n: usize,
+    // How many have arrived in this round.
+    arrived: AtomicUsize,
+    // Bumped by the last one to arrive, which lets the others go.
+    generation: AtomicU32,
+    wakers: ParkingMutex<Vec<Waker>>,
+}
+
+// Exactly one of the waiters in each round is the leader, the last to arrive.
+#[derive(Debug)]
+pub struct BarrierWaitResult(bool);
+
+impl BarrierWaitResult {
+    pub fn is_leader(&self) -> bool {
+        self.0
+    }
+}
+
+impl Barrier {
+    pub fn new(n: usize) -> Self {
+        Self {
+            n,
+            arrived: AtomicUsize::new(0),
+            generation: AtomicU32::new(0),
+            wakers: ParkingMutex::new(Vec::new()),
+        }
+    }
+
+    pub fn wait(&self) -> BarrierWaitResult {
+        let generation = self.generation.load(Ordering::Acquire);
+        if self.arrive() {
+            return BarrierWaitResult(true);
+        }
+        while self.generation.load(Ordering::Acquire) == generation {
+            wait(&self.generation, generation);
+        }
+        BarrierWaitResult(false)
+    }
+
+    // Dropping the future after its first poll still counts as having arrived.
+    pub fn wait_async(&self) -> BarrierWait<'_> {
+        BarrierWait {
+            barrier: self,
+            generation: None,
+        }
+    }
+
+    // Returns whether we're the last, in which case the round is over.
+    fn arrive(&self) -> bool {
+        // AcqRel so the leader sees what everyone did before arriving, and
+        // passes that on with the Release below.
+        if self.arrived.fetch_add(1, Ordering::AcqRel) + 1 < self.n {
+            return false;
+        }
+        // Nobody can arrive for the next round before seeing the new
+        // generation, and so this reset.
+        self.arrived.store(0, Ordering::Relaxed);
+        self.generation.fetch_add(1, Ordering::Release);
+        wake_all(&self.generation);
+        let wakers = std::mem::take(&mut *self.wakers.lock());
+        for waker in wakers {
+            waker.wake();
+        }
+        true
+    }
+}
+
+pub struct BarrierWait<'a> {
+    barrier: &'a Barrier,
+    // The round we arrived in, once we have.
+    generation: Option<u32>,
+}
+
+impl Future for BarrierWait<'_> {
+    type Output = BarrierWaitResult;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let barrier = self.barrier;
+        let generation = match self.generation {
+            Some(generation) => generation,
+            None => {
+                let generation = barrier.generation.load(Ordering::Acquire);
+                if barrier.arrive() {
+                    return Poll::Ready(BarrierWaitResult(true));
+                }
+                self.generation = Some(generation);
+                generation
+            }
+        };
+        if barrier.generation.load(Ordering::Acquire) != generation {
+            return Poll::Ready(BarrierWaitResult(false));
+        }
+        let mut wakers = barrier.wakers.lock();
+        // Like in `semaphore::Acquire`, don't pile up wakers if we're polled
+        // again before the round is over.
+        if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+        drop(wakers);
+        // The round may have ended before we were in the list.
+        if barrier.generation.load(Ordering::Acquire) != generation {
+            Poll::Ready(BarrierWaitResult(false))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::future::executor::new_executor_and_spawner;
+    use std::thread;
+
+    #[test]
+    fn rounds() {
+        const THREADS: usize = 5;
+        const ROUNDS: usize = 20;
+        let barrier: &'static _ = Box::leak(Box::new(Barrier::new(THREADS)));
+        let arrived: &'static _ = Box::leak(Box::new(
+            (0..ROUNDS).map(|_| AtomicUsize::new(0)).collect::<Vec<_>>(),
+        ));
+        let leaders: &'static _ = Box::leak(Box::new(
+            (0..ROUNDS).map(|_| AtomicUsize::new(0)).collect::<Vec<_>>(),
+        ));
+        let handles = (0..THREADS)
+            .map(|_| {
+                thread::spawn(move || {
+                    for round in 0..ROUNDS {
+                        arrived[round].fetch_add(1, Ordering::Relaxed);
+                        if barrier.wait().is_leader() {
+                            leaders[round].fetch_add(1, Ordering::Relaxed);
+                        }
+                        // Nobody gets past the barrier before everyone is there.
+                        assert_eq!(arrived[round].load(Ordering::Relaxed), THREADS);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert!(leaders.iter().all(|l| l.load(Ordering::Relaxed) == 1));
+    }
+
+    #[test]
+    fn repolls_dont_pile_up_wakers() {
+        let barrier = Barrier::new(2);
+        let mut wait = barrier.wait_async();
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        for _ in 0..3 {
+            assert!(Pin::new(&mut wait).poll(&mut cx).is_pending());
+        }
+        assert_eq!(barrier.wakers.lock().len(), 1);
+        assert!(barrier.wait().is_leader());
+        assert!(barrier.wakers.lock().is_empty());
+        assert!(Pin::new(&mut wait).poll(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn tasks_and_threads() {
+        let barrier: &'static _ = Box::leak(Box::new(Barrier::new(4)));
+        let leaders: &'static _ = Box::leak(Box::new(AtomicUsize::new(0)));
+        let (executor, spawner) = new_executor_and_spawner();
+        for _ in 0..3 {
+            spawner.spawn(async move {
+                for _ in 0..10 {
+                    if barrier.wait_async().await.is_leader() {
+                        leaders.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            });
+        }
+        drop(spawner);
+        let thread = thread::spawn(move || {
+            for _ in 0..10 {
+                if barrier.wait().is_leader() {
+                    leaders.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        });
+        executor.run();
+        thread.join().unwrap();
+        assert_eq!(leaders.load(Ordering::Relaxed), 10);
+    }
+}
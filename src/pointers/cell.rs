@@ -1,22 +1,36 @@
-use std::cell::UnsafeCell;
+use std::{cell::UnsafeCell, marker::PhantomData, mem, ptr};
 
-pub struct Cell<T> {
+/// ```compile_fail
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<crust_of_rust::pointers::cell::Cell<i32>>();
+/// ```
+// `repr(transparent)` so `&mut T` can be viewed as `&Cell<T>`, and `Cell<[T]>`
+// as `[Cell<T>]`.
+#[repr(transparent)]
+pub struct Cell<T: ?Sized> {
+    // `UnsafeCell` is `!Sync` already, but the whole point of `Cell` is that it
+    // is `!Sync`, so don't leave that to its auto traits. `*const ()` is neither
+    // `Send` nor `Sync`, so `Send` is given back below.
+    _not_sync: PhantomData<*const ()>,
     value: UnsafeCell<T>,
 }
 
+// SAFETY: Sending a `Cell` to another thread moves the value along with it,
+// nothing is shared between threads.
+unsafe impl<T: ?Sized + Send> Send for Cell<T> {}
+
 impl<T> Cell<T> {
     pub fn new(value: T) -> Self {
         Cell {
+            _not_sync: PhantomData,
             value: UnsafeCell::new(value),
         }
     }
 
     pub fn set(&self, value: T) {
-        // SAFETY: We know we're not invalidating any references,
-        // because we never give any out.
-        // SAFETY: This can cause data races if called from a separate thread,
-        // but `Cell` is `!Sync` so this won't happen.
-        unsafe { *self.value.get() = value };
+        // Drop the old value only after it's out of the cell, its `Drop` could
+        // be looking at the cell.
+        drop(self.replace(value));
     }
 
     pub fn get(&self) -> T
@@ -27,4 +41,117 @@ impl<T> Cell<T> {
         // but `Cell` is `!Sync` so this won't happen.
         unsafe { *self.value.get() }
     }
+
+    pub fn replace(&self, value: T) -> T {
+        // SAFETY: We know we're not invalidating any references,
+        // because we never give any out.
+        // SAFETY: This can cause data races if called from a separate thread,
+        // but `Cell` is `!Sync` so this won't happen.
+        unsafe { mem::replace(&mut *self.value.get(), value) }
+    }
+
+    pub fn take(&self) -> T
+    where
+        T: Default,
+    {
+        self.replace(T::default())
+    }
+
+    pub fn swap(&self, other: &Self) {
+        if ptr::eq(self, other) {
+            return;
+        }
+        // Two cells from `as_slice_of_cells` of overlapping `Cell<[T; N]>`s
+        // could partially overlap, and `ptr::swap` can't deal with that.
+        let (a, b) = (self.as_ptr() as usize, other.as_ptr() as usize);
+        if a.abs_diff(b) < mem::size_of::<T>() {
+            panic!("`Cell::swap` on overlapping cells");
+        }
+        // SAFETY: See `replace`, and the two cells don't overlap.
+        unsafe { ptr::swap(self.value.get(), other.value.get()) }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> Cell<T> {
+    pub fn from_mut(t: &mut T) -> &Cell<T> {
+        // SAFETY: `&mut` means we have exclusive access to `t` for as long as
+        // the `&Cell` lives, and `Cell<T>` has the same layout as `T`.
+        unsafe { &*(t as *mut T as *const Cell<T>) }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        // Having `&mut self` means nobody else can get at the value.
+        self.value.get_mut()
+    }
+
+    pub fn as_ptr(&self) -> *mut T {
+        self.value.get()
+    }
+}
+
+impl<T> Cell<[T]> {
+    pub fn as_slice_of_cells(&self) -> &[Cell<T>] {
+        // SAFETY: `Cell<T>` has the same layout as `T`, and a `Cell<[T]>` only
+        // allows what a `[Cell<T>]` does: replacing elements, but never handing
+        // out references into them.
+        unsafe { &*(self as *const Cell<[T]> as *const [Cell<T>]) }
+    }
+}
+
+impl<T: Default> Default for Cell<T> {
+    fn default() -> Self {
+        Cell::new(T::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn non_copy_values() {
+        let cell = Cell::new(String::from("a"));
+        assert_eq!(cell.replace(String::from("b")), "a");
+        assert_eq!(cell.take(), "b");
+        cell.set(String::from("c"));
+        assert_eq!(cell.into_inner(), "c");
+
+        let mut cell = Cell::new(vec![1]);
+        cell.get_mut().push(2);
+        assert_eq!(cell.take(), [1, 2]);
+    }
+
+    #[test]
+    fn swap() {
+        let a = Cell::new(String::from("a"));
+        let b = Cell::new(String::from("b"));
+        a.swap(&b);
+        a.swap(&a);
+        assert_eq!((a.take(), b.take()), (String::from("b"), String::from("a")));
+    }
+
+    #[test]
+    #[should_panic]
+    fn swap_overlapping() {
+        let mut array = [1, 2, 3];
+        let cells = Cell::from_mut(&mut array[..]).as_slice_of_cells();
+        // SAFETY: Both are views into `array`, which is borrowed by `cells`.
+        let first: &Cell<[i32; 2]> = unsafe { &*(cells.as_ptr() as *const Cell<[i32; 2]>) };
+        let second: &Cell<[i32; 2]> = unsafe { &*(cells[1..].as_ptr() as *const Cell<[i32; 2]>) };
+        first.swap(second);
+    }
+
+    #[test]
+    fn slice_of_cells() {
+        let mut values = [1, 2, 3];
+        let cells = Cell::from_mut(&mut values[..]).as_slice_of_cells();
+        for window in cells.windows(2) {
+            window[1].set(window[0].get() + window[1].get());
+        }
+        assert_eq!(values, [1, 3, 6]);
+    }
 }
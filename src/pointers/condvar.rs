@@ -0,0 +1,112 @@
+use super::{
+    parking::{wait, wake_all, wake_one},
+    unlock::MutexGuard,
+};
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    LockResult,
+};
+
+// Waiting for a condition on the value in an `unlock::Mutex`.
+//
+// Every notification bumps `seq`. A waiter reads it while still holding the
+// lock, so any notification that comes after it let go of the lock changes
+// `seq`, and the wait on the old value returns instead of missing it.
+pub struct Condvar {
+    seq: AtomicU32,
+}
+
+impl Condvar {
+    pub fn new() -> Self {
+        Self {
+            seq: AtomicU32::new(0),
+        }
+    }
+
+    // Unlocks the mutex, waits for a notification and locks it again. Like
+    // std's `Condvar`, it can also wake up without one, so check the condition
+    // in a loop, or use `wait_while`.
+    #[cfg_attr(feature = "deadlock-detection", track_caller)]
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> LockResult<MutexGuard<'a, T>> {
+        let seq = self.seq.load(Ordering::Relaxed);
+        let mutex = guard.lock;
+        drop(guard);
+        wait(&self.seq, seq);
+        mutex.lock()
+    }
+
+    #[cfg_attr(feature = "deadlock-detection", track_caller)]
+    pub fn wait_while<'a, T>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> LockResult<MutexGuard<'a, T>> {
+        while condition(&mut guard) {
+            guard = self.wait(guard)?;
+        }
+        Ok(guard)
+    }
+
+    pub fn notify_one(&self) {
+        self.seq.fetch_add(1, Ordering::Relaxed);
+        wake_one(&self.seq);
+    }
+
+    pub fn notify_all(&self) {
+        self.seq.fetch_add(1, Ordering::Relaxed);
+        wake_all(&self.seq);
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pointers::unlock::Mutex;
+    use std::{collections::VecDeque, thread};
+
+    #[test]
+    fn producer_consumer() {
+        let queue: &'static _ = Box::leak(Box::new(Mutex::new(VecDeque::new())));
+        let available: &'static _ = Box::leak(Box::new(Condvar::new()));
+        let consumer = thread::spawn(move || {
+            let mut sum = 0;
+            for _ in 0..1000 {
+                let mut q = available
+                    .wait_while(queue.lock().unwrap(), |q| q.is_empty())
+                    .unwrap();
+                sum += q.pop_front().unwrap();
+            }
+            sum
+        });
+        for i in 0..1000 {
+            queue.with_lock(|q| q.push_back(i));
+            available.notify_one();
+        }
+        assert_eq!(consumer.join().unwrap(), (0..1000).sum::<i32>());
+    }
+
+    #[test]
+    fn notify_all() {
+        let started: &'static _ = Box::leak(Box::new(Mutex::new(false)));
+        let cond: &'static _ = Box::leak(Box::new(Condvar::new()));
+        let waiters = (0..5)
+            .map(|_| {
+                thread::spawn(move || {
+                    let started = cond.wait_while(started.lock().unwrap(), |s| !*s);
+                    assert!(*started.unwrap());
+                })
+            })
+            .collect::<Vec<_>>();
+        started.with_lock(|s| *s = true);
+        cond.notify_all();
+        for waiter in waiters {
+            waiter.join().unwrap();
+        }
+    }
+}
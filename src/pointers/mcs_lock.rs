@@ -0,0 +1,230 @@
+use std::{
+    cell::UnsafeCell,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, Ordering},
+    thread,
+};
+
+// Every waiter in an MCS lock (Mellor-Crummey and Scott) spins on a node of its
+// own instead of on the lock, and the nodes form a queue: the lock only points
+// at the last one, and each holder hands the lock to the next node directly.
+// So it's FIFO like `TicketLock`, but an unlock only touches the cache line of
+// the thread that gets the lock next, not those of all waiters.
+pub struct McsLock<T> {
+    v: UnsafeCell<T>,
+    tail: AtomicPtr<Node>,
+}
+
+struct Node {
+    next: AtomicPtr<Node>,
+    locked: AtomicBool,
+}
+
+unsafe impl<T: Send> Sync for McsLock<T> {}
+
+impl<T> McsLock<T> {
+    pub fn new(t: T) -> Self {
+        Self {
+            v: UnsafeCell::new(t),
+            tail: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    pub fn lock(&self) -> McsLockGuard<'_, T> {
+        let node = self.enqueue();
+        self.wait_for(node)
+    }
+
+    // Gets in line, and returns our node, which is unlocked once it's our turn.
+    fn enqueue(&self) -> *mut Node {
+        // The guard can move, so the node lives on the heap, where it stays
+        // put for as long as the others may look at it.
+        let node = Box::into_raw(Box::new(Node {
+            next: AtomicPtr::new(ptr::null_mut()),
+            locked: AtomicBool::new(true),
+        }));
+        // Release publishes our node to whoever comes after us, Acquire is for
+        // the node of whoever was before us.
+        let prev = self.tail.swap(node, Ordering::AcqRel);
+        if prev.is_null() {
+            // Nobody is before us to unlock it. The swap already synchronized
+            // with the last holder, so Relaxed is enough.
+            //
+            // SAFETY: Our node is ours until the guard is dropped.
+            unsafe { (*node).locked.store(false, Ordering::Relaxed) };
+        } else {
+            // SAFETY: `prev` isn't freed before its owner handed over the lock,
+            // and that waits until we've linked ourselves in here.
+            unsafe { (*prev).next.store(node, Ordering::Release) };
+        }
+        node
+    }
+
+    // `node` must come from `enqueue` on this lock.
+    fn wait_for(&self, node: *mut Node) -> McsLockGuard<'_, T> {
+        // SAFETY: Our node is ours until the guard is dropped.
+        while unsafe { (*node).locked.load(Ordering::Acquire) } {
+            thread::yield_now();
+        }
+        McsLockGuard::new(self, node)
+    }
+
+    pub fn try_lock(&self) -> Option<McsLockGuard<'_, T>> {
+        let node = Box::into_raw(Box::new(Node {
+            next: AtomicPtr::new(ptr::null_mut()),
+            locked: AtomicBool::new(true),
+        }));
+        match self
+            .tail
+            .compare_exchange(ptr::null_mut(), node, Ordering::AcqRel, Ordering::Relaxed)
+        {
+            Ok(_) => Some(McsLockGuard::new(self, node)),
+            Err(_) => {
+                // SAFETY: The node was never published.
+                drop(unsafe { Box::from_raw(node) });
+                None
+            }
+        }
+    }
+
+    pub fn with_lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut self.lock())
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.v.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.v.into_inner()
+    }
+}
+
+/// ```compile_fail
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<crust_of_rust::pointers::mcs_lock::McsLockGuard<'static, std::cell::Cell<i32>>>();
+/// ```
+pub struct McsLockGuard<'a, T> {
+    lock: &'a McsLock<T>,
+    node: *mut Node,
+    // Like `unlock::MutexGuard`, so sharing the guard needs `T: Sync`. The
+    // node pointer would make it `!Sync` anyway, but not on purpose.
+    _not_sync: PhantomData<*const ()>,
+}
+
+// SAFETY: Sharing the guard only shares a `&T`, the node is only touched on drop.
+unsafe impl<T: Sync> Sync for McsLockGuard<'_, T> {}
+
+impl<'a, T> McsLockGuard<'a, T> {
+    // Must only be called once `node` holds the lock.
+    fn new(lock: &'a McsLock<T>, node: *mut Node) -> Self {
+        McsLockGuard {
+            lock,
+            node,
+            _not_sync: PhantomData,
+        }
+    }
+}
+
+impl<T> Deref for McsLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        // Safety: see `deref_mut`.
+        unsafe { &*self.lock.v.get() }
+    }
+}
+
+impl<T> DerefMut for McsLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: we hold the lock, therefore we can create a mutable reference.
+        unsafe { &mut *self.lock.v.get() }
+    }
+}
+
+impl<T> Drop for McsLockGuard<'_, T> {
+    fn drop(&mut self) {
+        // SAFETY: The node is ours, and the others only use it until they've
+        // linked themselves in, which we wait for below.
+        let node = unsafe { &*self.node };
+        let mut next = node.next.load(Ordering::Acquire);
+        if next.is_null() {
+            // Nobody is waiting, unless someone already swapped themselves
+            // into `tail` and is about to link in.
+            if self
+                .lock
+                .tail
+                .compare_exchange(
+                    self.node,
+                    ptr::null_mut(),
+                    Ordering::Release,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+            {
+                // SAFETY: Nobody else can get at the node anymore.
+                drop(unsafe { Box::from_raw(self.node) });
+                return;
+            }
+            loop {
+                next = node.next.load(Ordering::Acquire);
+                if !next.is_null() {
+                    break;
+                }
+                thread::yield_now();
+            }
+        }
+        // SAFETY: `next` waits for this store, so it's still around. After it,
+        // nobody looks at our node again.
+        unsafe {
+            (*next).locked.store(false, Ordering::Release);
+            drop(Box::from_raw(self.node));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gogo() {
+        let l: &'static _ = Box::leak(Box::new(McsLock::new(0)));
+        crate::pointers::test_util::assert_bounded_wait(move |enqueued: &dyn Fn()| {
+            let node = l.enqueue();
+            enqueued();
+            l.wait_for(node)
+        });
+    }
+
+    #[test]
+    fn guard_is_sync_for_sync_values() {
+        fn assert_sync<T: Sync>() {}
+        assert_sync::<McsLockGuard<'static, i32>>();
+    }
+
+    #[test]
+    fn first_come_first_served() {
+        let l: &'static _ = Box::leak(Box::new(McsLock::new(Vec::new())));
+        let guard = l.lock();
+        assert!(l.try_lock().is_none());
+        let mut tail = l.tail.load(Ordering::Relaxed);
+        let handles = (0..5)
+            .map(|id| {
+                let handle = thread::spawn(move || l.with_lock(|v| v.push(id)));
+                // Wait until it's in the queue before the next one asks.
+                while l.tail.load(Ordering::Relaxed) == tail {
+                    thread::yield_now();
+                }
+                tail = l.tail.load(Ordering::Relaxed);
+                handle
+            })
+            .collect::<Vec<_>>();
+        drop(guard);
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(*l.try_lock().unwrap(), [0, 1, 2, 3, 4]);
+    }
+}
@@ -1,6 +1,30 @@
+pub mod alloc;
 pub mod arc;
+pub mod atomic_arc;
+pub mod barrier;
 pub mod cell;
+pub mod condvar;
+pub mod mcs_lock;
+pub mod once;
+pub mod parking;
 pub mod rc;
 pub mod ref_cell;
+pub mod rwlock;
+pub mod semaphore;
+pub mod small_vec;
+#[cfg(test)]
+mod test_util;
+pub mod ticket_lock;
 pub mod unlock;
 pub mod vec;
+pub mod vec_deque;
+
+// Replaces the address of a possibly fat pointer, keeping its metadata (slice
+// length or vtable). This is how we build pointers to unsized blocks on stable.
+//
+// SAFETY: `data` must be valid for the metadata of `ptr`.
+unsafe fn set_data_ptr<T: ?Sized, U>(mut ptr: *mut T, data: *mut U) -> *mut T {
+    // The address is the first word of both thin and fat pointers.
+    std::ptr::write(&mut ptr as *mut *mut T as *mut *mut u8, data as *mut u8);
+    ptr
+}
@@ -0,0 +1,438 @@
+use super::parking::{wait, wake_all};
+use std::{
+    cell::UnsafeCell,
+    convert::Infallible,
+    fmt,
+    mem::MaybeUninit,
+    ops::Deref,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+const INCOMPLETE: u32 = 0;
+const RUNNING: u32 = 1;
+const COMPLETE: u32 = 2;
+
+// Runs a closure once, no matter how many threads call it at the same time.
+// The others wait until it's done. If the closure panics or fails, the next
+// caller gets to try.
+pub struct Once {
+    state: AtomicU32,
+}
+
+impl Once {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU32::new(INCOMPLETE),
+        }
+    }
+
+    pub fn call_once(&self, f: impl FnOnce()) {
+        let _ = self.try_call_once(|| {
+            f();
+            Ok::<_, Infallible>(())
+        });
+    }
+
+    pub fn is_completed(&self) -> bool {
+        // Acquire so that whatever the closure did is visible once we've seen
+        // it's done.
+        self.state.load(Ordering::Acquire) == COMPLETE
+    }
+
+    // Returns once `f` has run successfully, here or somewhere else, or with
+    // its error if it ran here and failed.
+    fn try_call_once<E>(&self, f: impl FnOnce() -> Result<(), E>) -> Result<(), E> {
+        loop {
+            match self.state.compare_exchange(
+                INCOMPLETE,
+                RUNNING,
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break,
+                Err(COMPLETE) => return Ok(()),
+                Err(_) => wait(&self.state, RUNNING),
+            }
+        }
+
+        // Hands the `Once` to the next caller if `f` panics or fails.
+        struct Reset<'a>(&'a Once);
+        impl Drop for Reset<'_> {
+            fn drop(&mut self) {
+                self.0.state.store(INCOMPLETE, Ordering::Relaxed);
+                wake_all(&self.0.state);
+            }
+        }
+
+        let reset = Reset(self);
+        f()?;
+        std::mem::forget(reset);
+        // Release pairs with the Acquire loads above and in `is_completed`.
+        self.state.store(COMPLETE, Ordering::Release);
+        wake_all(&self.state);
+        Ok(())
+    }
+}
+
+impl Default for Once {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// A value that's set at most once, and from then on can be shared between
+// threads without any locking.
+pub struct OnceCell<T> {
+    once: Once,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+// `get_or_init` on one thread may move a `T` in that was created elsewhere,
+// so `Sync` needs `T: Send` as well.
+unsafe impl<T: Send + Sync> Sync for OnceCell<T> {}
+unsafe impl<T: Send> Send for OnceCell<T> {}
+
+impl<T> OnceCell<T> {
+    pub const fn new() -> Self {
+        Self {
+            once: Once::new(),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        if self.once.is_completed() {
+            // SAFETY: The value was written before the `Once` completed, and
+            // is never written again.
+            Some(unsafe { (*self.value.get()).assume_init_ref() })
+        } else {
+            None
+        }
+    }
+
+    // Gives the value back if the cell was already set.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        let mut value = Some(value);
+        self.get_or_init(|| value.take().unwrap());
+        match value {
+            None => Ok(()),
+            Some(value) => Err(value),
+        }
+    }
+
+    // Calling `get_or_init` on the same cell from `f` deadlocks.
+    pub fn get_or_init(&self, f: impl FnOnce() -> T) -> &T {
+        match self.get_or_try_init(|| Ok::<_, Infallible>(f())) {
+            Ok(value) => value,
+            Err(never) => match never {},
+        }
+    }
+
+    pub fn get_or_try_init<E>(&self, f: impl FnOnce() -> Result<T, E>) -> Result<&T, E> {
+        if let Some(value) = self.get() {
+            return Ok(value);
+        }
+        self.once.try_call_once(|| {
+            let value = f()?;
+            // SAFETY: We're the only ones running, and nobody reads the value
+            // before the `Once` completes.
+            unsafe { (*self.value.get()).write(value) };
+            Ok(())
+        })?;
+        Ok(self.get().unwrap())
+    }
+
+    pub fn into_inner(mut self) -> Option<T> {
+        self.take()
+    }
+
+    pub fn take(&mut self) -> Option<T> {
+        if self.once.is_completed() {
+            self.once = Once::new();
+            // SAFETY: The value is initialized, and resetting the `Once` above
+            // makes sure nobody reads or drops it again.
+            Some(unsafe { self.value.get_mut().assume_init_read() })
+        } else {
+            None
+        }
+    }
+}
+
+impl<T> Default for OnceCell<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for OnceCell<T> {
+    fn drop(&mut self) {
+        drop(self.take());
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for OnceCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.get() {
+            Some(value) => f.debug_tuple("OnceCell").field(value).finish(),
+            None => f.write_str("OnceCell(<uninit>)"),
+        }
+    }
+}
+
+// A value that's computed by `F` the first time it's used.
+pub struct Lazy<T, F = fn() -> T> {
+    cell: OnceCell<T>,
+    init: UnsafeCell<Option<F>>,
+}
+
+// `init` is only touched by the one thread that gets to initialize `cell`.
+unsafe impl<T: Send + Sync, F: Send> Sync for Lazy<T, F> {}
+
+impl<T, F: FnOnce() -> T> Lazy<T, F> {
+    pub const fn new(init: F) -> Self {
+        Self {
+            cell: OnceCell::new(),
+            init: UnsafeCell::new(Some(init)),
+        }
+    }
+
+    pub fn force(this: &Self) -> &T {
+        this.cell.get_or_init(|| {
+            // SAFETY: Only the thread that runs the initialization gets here.
+            match unsafe { (*this.init.get()).take() } {
+                Some(init) => init(),
+                None => panic!("`Lazy` instance has previously been poisoned"),
+            }
+        })
+    }
+}
+
+impl<T, F: FnOnce() -> T> Deref for Lazy<T, F> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        Lazy::force(self)
+    }
+}
+
+pub mod unsync {
+    use crate::pointers::cell::Cell;
+    use std::{fmt, ops::Deref};
+
+    // `super::OnceCell` for a single thread, so without any atomics.
+    pub struct OnceCell<T> {
+        value: Cell<Option<T>>,
+    }
+
+    impl<T> OnceCell<T> {
+        pub fn new() -> Self {
+            Self {
+                value: Cell::new(None),
+            }
+        }
+
+        pub fn get(&self) -> Option<&T> {
+            // SAFETY: Once the value is set it's never written again while we
+            // have `&self`, so references into it stay valid.
+            unsafe { (*self.value.as_ptr()).as_ref() }
+        }
+
+        pub fn set(&self, value: T) -> Result<(), T> {
+            if self.get().is_some() {
+                return Err(value);
+            }
+            // Nothing can point into the `None` we replace.
+            self.value.set(Some(value));
+            Ok(())
+        }
+
+        pub fn get_or_init(&self, f: impl FnOnce() -> T) -> &T {
+            match self.get_or_try_init(|| Ok::<_, std::convert::Infallible>(f())) {
+                Ok(value) => value,
+                Err(never) => match never {},
+            }
+        }
+
+        pub fn get_or_try_init<E>(&self, f: impl FnOnce() -> Result<T, E>) -> Result<&T, E> {
+            if let Some(value) = self.get() {
+                return Ok(value);
+            }
+            let value = f()?;
+            // `f` could have set the cell itself, and overwriting it would
+            // invalidate the reference that call gave out.
+            if self.set(value).is_err() {
+                panic!("reentrant init");
+            }
+            Ok(self.get().unwrap())
+        }
+
+        pub fn into_inner(self) -> Option<T> {
+            self.value.into_inner()
+        }
+
+        pub fn take(&mut self) -> Option<T> {
+            self.value.take()
+        }
+    }
+
+    impl<T> Default for OnceCell<T> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl<T: fmt::Debug> fmt::Debug for OnceCell<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self.get() {
+                Some(value) => f.debug_tuple("OnceCell").field(value).finish(),
+                None => f.write_str("OnceCell(<uninit>)"),
+            }
+        }
+    }
+
+    // `super::Lazy` for a single thread.
+    pub struct Lazy<T, F = fn() -> T> {
+        cell: OnceCell<T>,
+        init: Cell<Option<F>>,
+    }
+
+    impl<T, F: FnOnce() -> T> Lazy<T, F> {
+        pub fn new(init: F) -> Self {
+            Self {
+                cell: OnceCell::new(),
+                init: Cell::new(Some(init)),
+            }
+        }
+
+        pub fn force(this: &Self) -> &T {
+            this.cell.get_or_init(|| match this.init.take() {
+                Some(init) => init(),
+                None => panic!("`Lazy` instance has previously been poisoned"),
+            })
+        }
+    }
+
+    impl<T, F: FnOnce() -> T> Deref for Lazy<T, F> {
+        type Target = T;
+        fn deref(&self) -> &Self::Target {
+            Lazy::force(self)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        panic,
+        sync::{atomic::AtomicUsize, Barrier},
+        thread,
+    };
+
+    #[test]
+    fn init_race() {
+        static CELL: OnceCell<String> = OnceCell::new();
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        let start: &'static _ = Box::leak(Box::new(Barrier::new(10)));
+        let handles = (0..10)
+            .map(|i| {
+                thread::spawn(move || {
+                    start.wait();
+                    let value = CELL.get_or_init(|| {
+                        CALLS.fetch_add(1, Ordering::Relaxed);
+                        // Give the others time to pile up behind us.
+                        thread::yield_now();
+                        format!("thread {}", i)
+                    });
+                    value.clone()
+                })
+            })
+            .collect::<Vec<_>>();
+        let seen = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+        assert!(seen.iter().all(|v| v == CELL.get().unwrap()));
+        assert_eq!(CELL.set(String::new()), Err(String::new()));
+    }
+
+    #[test]
+    fn failed_init_retries() {
+        let cell = OnceCell::new();
+        assert_eq!(cell.get_or_try_init(|| Err("nope")), Err("nope"));
+        assert!(cell.get().is_none());
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            cell.get_or_init(|| panic!("halfway through"))
+        }));
+        assert!(result.is_err());
+        assert_eq!(cell.get_or_try_init(|| Ok::<_, ()>(1)), Ok(&1));
+        assert_eq!(cell.get_or_try_init(|| Err(())), Ok(&1));
+        assert_eq!(cell.into_inner(), Some(1));
+    }
+
+    #[test]
+    fn once() {
+        let once = Once::new();
+        let mut calls = 0;
+        once.call_once(|| calls += 1);
+        once.call_once(|| calls += 1);
+        assert!(once.is_completed());
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn drops_value() {
+        let value = std::rc::Rc::new(());
+        let cell = OnceCell::new();
+        // `Rc` isn't `Send`, but that only matters for sharing the cell.
+        cell.set(value.clone()).unwrap();
+        drop(cell);
+        assert_eq!(std::rc::Rc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn lazy_race() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        static VALUE: Lazy<Vec<usize>> = Lazy::new(|| {
+            CALLS.fetch_add(1, Ordering::Relaxed);
+            (0..100).collect()
+        });
+        let handles = (0..10)
+            .map(|_| thread::spawn(|| VALUE.iter().sum::<usize>()))
+            .collect::<Vec<_>>();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 4950);
+        }
+        assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn unsync() {
+        let cell = unsync::OnceCell::new();
+        assert!(cell.get().is_none());
+        let first = cell.get_or_init(|| String::from("first"));
+        assert_eq!(
+            cell.set(String::from("second")),
+            Err(String::from("second"))
+        );
+        assert_eq!(first, "first");
+
+        let calls = crate::pointers::cell::Cell::new(0);
+        let lazy = unsync::Lazy::new(|| {
+            calls.set(calls.get() + 1);
+            5
+        });
+        assert_eq!(*lazy + *lazy, 10);
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "reentrant init")]
+    fn unsync_reentrant_init() {
+        let cell = unsync::OnceCell::new();
+        cell.get_or_init(|| {
+            let _ = cell.get_or_init(|| 1);
+            2
+        });
+    }
+}
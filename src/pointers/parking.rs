@@ -0,0 +1,310 @@
+use std::{
+    cell::UnsafeCell,
+    hint,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+// Also used by the other blocking primitives in `pointers`.
+#[cfg(not(target_os = "linux"))]
+pub(super) use fallback::{wait, wake_all, wake_one};
+#[cfg(target_os = "linux")]
+pub(super) use futex::{wait, wake_all, wake_one};
+
+// Blocks while `futex` holds `expected`. May also return spuriously, so callers
+// check the value again. `wake_one` wakes (at least) one thread waiting on `futex`
+// after its value was changed, `wake_all` wakes all of them.
+#[cfg(target_os = "linux")]
+mod futex {
+    use std::{
+        ptr,
+        sync::atomic::{AtomicU32, Ordering},
+    };
+
+    pub fn wait(futex: &AtomicU32, expected: u32) {
+        // The kernel checks the value again and goes to sleep atomically, so a
+        // wake that comes in after we looked at the value isn't lost.
+        if futex.load(Ordering::Relaxed) == expected {
+            // SAFETY: `futex` is a valid, aligned 32-bit word for as long as we
+            // wait on it. EINTR and EAGAIN are spurious wake ups for the caller.
+            unsafe {
+                libc::syscall(
+                    libc::SYS_futex,
+                    futex.as_ptr(),
+                    libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG,
+                    expected,
+                    ptr::null::<libc::timespec>(),
+                );
+            }
+        }
+    }
+
+    pub fn wake_one(futex: &AtomicU32) {
+        // SAFETY: Waking never touches the memory, only the kernel's wait
+        // queue for that address.
+        unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                futex.as_ptr(),
+                libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG,
+                1,
+            );
+        }
+    }
+
+    pub fn wake_all(futex: &AtomicU32) {
+        // SAFETY: See `wake_one`.
+        unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                futex.as_ptr(),
+                libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG,
+                i32::MAX,
+            );
+        }
+    }
+}
+
+// Without futexes, threads sleep on a condition variable picked by the address
+// they wait on. Addresses share buckets, so a wake has to wake everyone in the
+// bucket, and the others find their value unchanged and go back to sleep.
+#[cfg(any(not(target_os = "linux"), test))]
+mod fallback {
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Condvar, Mutex,
+    };
+
+    struct Bucket {
+        lock: Mutex<()>,
+        cond: Condvar,
+    }
+
+    #[allow(clippy::declare_interior_mutable_const)]
+    const BUCKET: Bucket = Bucket {
+        lock: Mutex::new(()),
+        cond: Condvar::new(),
+    };
+    static BUCKETS: [Bucket; 64] = [BUCKET; 64];
+
+    fn bucket(futex: &AtomicU32) -> &'static Bucket {
+        let addr = futex as *const AtomicU32 as usize;
+        &BUCKETS[(addr / 4) % BUCKETS.len()]
+    }
+
+    pub fn wait(futex: &AtomicU32, expected: u32) {
+        let bucket = bucket(futex);
+        let guard = bucket.lock.lock().unwrap();
+        // The waker changes the value before taking the bucket lock, so if
+        // it's still unchanged here, the wake can't happen until we're waiting.
+        if futex.load(Ordering::Relaxed) == expected {
+            drop(bucket.cond.wait(guard).unwrap());
+        }
+    }
+
+    pub fn wake_one(futex: &AtomicU32) {
+        let bucket = bucket(futex);
+        let _guard = bucket.lock.lock().unwrap();
+        bucket.cond.notify_all();
+    }
+
+    pub fn wake_all(futex: &AtomicU32) {
+        wake_one(futex);
+    }
+}
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+// Locked, and there may be threads waiting, so unlocking has to wake one.
+const CONTENDED: u32 = 2;
+
+// A Mutex that puts waiting threads to sleep instead of having them spin like
+// `unlock::Mutex` does. Unlocking without waiters is just as cheap: only a lock
+// that saw `CONTENDED` pays for the wake up.
+pub struct ParkingMutex<T> {
+    v: UnsafeCell<T>,
+    state: AtomicU32,
+}
+
+unsafe impl<T: Send> Sync for ParkingMutex<T> {}
+
+impl<T> ParkingMutex<T> {
+    pub fn new(t: T) -> Self {
+        Self {
+            v: UnsafeCell::new(t),
+            state: AtomicU32::new(UNLOCKED),
+        }
+    }
+
+    pub fn lock(&self) -> ParkingMutexGuard<'_, T> {
+        if self
+            .state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            self.lock_contended();
+        }
+        ParkingMutexGuard::new(self)
+    }
+
+    #[cold]
+    fn lock_contended(&self) {
+        // Short critical sections are often over before a sleep would even
+        // start, so spin for a bit first, but only while nobody sleeps.
+        for _ in 0..100 {
+            if self.state.load(Ordering::Relaxed) != LOCKED {
+                break;
+            }
+            hint::spin_loop();
+        }
+        if self
+            .state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            return;
+        }
+        // We can't tell if we're the only waiter, so once we get the lock it
+        // stays `CONTENDED` and the next unlock wakes someone, possibly for
+        // nothing. That's cheaper than losing a wake up.
+        while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+            wait(&self.state, CONTENDED);
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<ParkingMutexGuard<'_, T>> {
+        self.state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| ParkingMutexGuard::new(self))
+    }
+
+    pub fn with_lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut self.lock())
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.v.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.v.into_inner()
+    }
+}
+
+/// ```compile_fail
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<crust_of_rust::pointers::parking::ParkingMutexGuard<'static, std::cell::Cell<i32>>>();
+/// ```
+pub struct ParkingMutexGuard<'a, T> {
+    lock: &'a ParkingMutex<T>,
+    // Like `unlock::MutexGuard`, so sharing the guard needs `T: Sync`.
+    _not_sync: PhantomData<*const ()>,
+}
+
+// SAFETY: Sharing the guard only shares a `&T`.
+unsafe impl<T: Sync> Sync for ParkingMutexGuard<'_, T> {}
+
+impl<'a, T> ParkingMutexGuard<'a, T> {
+    // Must only be called with the lock held.
+    fn new(lock: &'a ParkingMutex<T>) -> Self {
+        ParkingMutexGuard {
+            lock,
+            _not_sync: PhantomData,
+        }
+    }
+}
+
+impl<T> Deref for ParkingMutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        // Safety: see `deref_mut`.
+        unsafe { &*self.lock.v.get() }
+    }
+}
+
+impl<T> DerefMut for ParkingMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: we hold the lock, therefore we can create a mutable reference.
+        unsafe { &mut *self.lock.v.get() }
+    }
+}
+
+impl<T> Drop for ParkingMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        if self.lock.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            wake_one(&self.lock.state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::atomic::AtomicBool, thread, time::Duration};
+
+    #[test]
+    fn gogo() {
+        let l: &'static _ = Box::leak(Box::new(ParkingMutex::new(0)));
+        let handles = (0..10)
+            .map(|_| {
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        l.with_lock(|v| {
+                            *v += 1;
+                        })
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(l.with_lock(|v| *v), 10 * 1000);
+    }
+
+    #[test]
+    fn guard_is_sync_for_sync_values() {
+        fn assert_sync<T: Sync>() {}
+        assert_sync::<ParkingMutexGuard<'static, i32>>();
+    }
+
+    #[test]
+    fn sleeps_until_unlocked() {
+        let l: &'static _ = Box::leak(Box::new(ParkingMutex::new(0)));
+        let mut guard = l.lock();
+        assert!(l.try_lock().is_none());
+        let waiter = thread::spawn(move || *l.lock() += 1);
+        while l.state.load(Ordering::Relaxed) != CONTENDED {
+            thread::yield_now();
+        }
+        *guard += 1;
+        drop(guard);
+        waiter.join().unwrap();
+        assert_eq!(*l.lock(), 2);
+        assert_eq!(l.state.load(Ordering::Relaxed), UNLOCKED);
+    }
+
+    #[test]
+    fn fallback_wakes_waiters() {
+        let word: &'static _ = Box::leak(Box::new(AtomicU32::new(0)));
+        let woken: &'static _ = Box::leak(Box::new(AtomicBool::new(false)));
+        let waiter = thread::spawn(move || {
+            while word.load(Ordering::Acquire) == 0 {
+                fallback::wait(word, 0);
+            }
+            woken.store(true, Ordering::Relaxed);
+        });
+        thread::sleep(Duration::from_millis(10));
+        assert!(!woken.load(Ordering::Relaxed));
+        word.store(1, Ordering::Release);
+        fallback::wake_one(word);
+        waiter.join().unwrap();
+        assert!(woken.load(Ordering::Relaxed));
+        // A wait on a value that already changed returns right away, and
+        // waking nobody is fine too.
+        fallback::wait(word, 0);
+        fallback::wake_all(word);
+    }
+}
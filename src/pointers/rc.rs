@@ -1,12 +1,70 @@
-use super::cell::Cell;
-use std::{marker::PhantomData, ops::Deref, ptr::NonNull};
+mod cycle;
 
-struct RcInner<T> {
-    value: T,
-    refcount: Cell<usize>,
+pub use cycle::{collect_cycles, register_root, Trace, Tracer};
+
+use super::{cell::Cell, set_data_ptr};
+use std::{
+    alloc::{self, Layout},
+    borrow::Borrow,
+    cmp::Ordering,
+    fmt,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+    mem::ManuallyDrop,
+    ops::Deref,
+    ptr::{self, NonNull},
+};
+
+// `repr(C)` so the offset of `value` can be computed from its layout alone,
+// which we need to allocate and find the block of an unsized `value`.
+#[repr(C)]
+struct RcInner<T: ?Sized> {
+    strong: Cell<usize>,
+    // All strong pointers together hold one weak reference, so the block is
+    // freed exactly once: either by the last `Rc` or by the last `Weak`.
+    weak: Cell<usize>,
+    // `value` is dropped when the last `Rc` goes away, but the allocation lives
+    // on until the last `Weak` goes away too, so we can't let `Box` drop it.
+    // It has to be the last field since it may be unsized.
+    value: ManuallyDrop<T>,
+}
+
+// While `collect_cycles` drops the values of a garbage cycle, their strong
+// counts are offset by this. The `Rc`s between them can then be dropped
+// without dropping anything twice, and their `Weak`s can't upgrade anymore.
+const COLLECTING: usize = usize::MAX / 2;
+
+impl<T: ?Sized> RcInner<T> {
+    // The number of `Rc`s, even while being collected.
+    fn strong(&self) -> usize {
+        let count = self.strong.get();
+        count.checked_sub(COLLECTING).unwrap_or(count)
+    }
+
+    // Whether the value was dropped, or is being dropped by the collector.
+    fn is_dead(&self) -> bool {
+        let count = self.strong.get();
+        count == 0 || count >= COLLECTING
+    }
+
+    fn inc_strong(&self) {
+        let count = self.strong.get();
+        if count == usize::MAX {
+            std::process::abort();
+        }
+        self.strong.set(count + 1);
+    }
+
+    fn inc_weak(&self) {
+        let count = self.weak.get();
+        if count == usize::MAX {
+            std::process::abort();
+        }
+        self.weak.set(count + 1);
+    }
 }
 
-pub struct Rc<T> {
+pub struct Rc<T: ?Sized> {
     inner: NonNull<RcInner<T>>,
     _marker: PhantomData<RcInner<T>>,
 }
@@ -14,8 +72,9 @@ pub struct Rc<T> {
 impl<T> Rc<T> {
     pub fn new(value: T) -> Self {
         let inner = Box::new(RcInner {
-            value,
-            refcount: Cell::new(1),
+            strong: Cell::new(1),
+            weak: Cell::new(1),
+            value: ManuallyDrop::new(value),
         });
 
         Rc {
@@ -26,19 +85,103 @@ impl<T> Rc<T> {
     }
 }
 
-impl<T> Deref for Rc<T> {
+impl<T: ?Sized> Rc<T> {
+    // Allocates a block for a value with the layout and metadata of `ptr`, with
+    // both counts set to one. The value itself is left uninitialized.
+    //
+    // SAFETY: `ptr` must point to a valid value, it's only used for its layout.
+    unsafe fn allocate_for_ptr(ptr: *const T) -> NonNull<RcInner<T>> {
+        let (layout, _) = Layout::new::<RcInner<()>>()
+            .extend(Layout::for_value(&*ptr))
+            .unwrap();
+        let layout = layout.pad_to_align();
+        let mem = alloc::alloc(layout);
+        if mem.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+
+        let inner = set_data_ptr(ptr as *mut RcInner<T>, mem);
+        ptr::write(&mut (*inner).strong, Cell::new(1));
+        ptr::write(&mut (*inner).weak, Cell::new(1));
+        NonNull::new_unchecked(inner)
+    }
+
+    // Moves the value behind `ptr` into a new block, the caller is responsible
+    // for freeing the memory of `ptr` without dropping the value.
+    unsafe fn copy_from_ptr(ptr: *const T) -> Self {
+        let inner = Self::allocate_for_ptr(ptr);
+        let size = std::mem::size_of_val(&*ptr);
+        ptr::copy_nonoverlapping(
+            ptr as *const u8,
+            &mut (*inner.as_ptr()).value as *mut ManuallyDrop<T> as *mut u8,
+            size,
+        );
+        Rc {
+            inner,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn into_raw(this: Self) -> *const T {
+        let this = ManuallyDrop::new(this);
+        &**this as *const T
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must come from `Rc::into_raw` on an `Rc<U>`, where `U` is either `T`
+    /// or a type that unsizes to `T` (e.g. `U: Trait` and `T = dyn Trait`).
+    pub unsafe fn from_raw(ptr: *const T) -> Self {
+        // Find the block from the value, we can't use the layout of `RcInner<T>`
+        // directly since the offset of `value` depends on the alignment of `T`.
+        let (_, offset) = Layout::new::<RcInner<()>>()
+            .extend(Layout::for_value(&*ptr))
+            .unwrap();
+        let inner = (ptr as *mut RcInner<T>).wrapping_byte_sub(offset);
+        Rc {
+            inner: NonNull::new_unchecked(inner),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn downgrade(this: &Self) -> Weak<T> {
+        this.inner().inc_weak();
+        Weak {
+            inner: Some(this.inner),
+        }
+    }
+
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        // Only compare addresses, the metadata of two `dyn Trait` pointers
+        // to the same value may differ.
+        this.inner.as_ptr() as *const () == other.inner.as_ptr() as *const ()
+    }
+
+    pub fn strong_count(this: &Self) -> usize {
+        this.inner().strong()
+    }
+
+    pub fn weak_count(this: &Self) -> usize {
+        // Don't count the weak reference held by the strong pointers.
+        this.inner().weak.get() - 1
+    }
+
+    fn inner(&self) -> &RcInner<T> {
+        // SAFETY: The block is alive as long as there is a strong pointer to it.
+        unsafe { self.inner.as_ref() }
+    }
+}
+
+impl<T: ?Sized> Deref for Rc<T> {
     type Target = T;
     fn deref(&self) -> &Self::Target {
-        let inner = unsafe { self.inner.as_ref() };
-        &inner.value
+        &self.inner().value
     }
 }
 
-impl<T> Clone for Rc<T> {
+impl<T: ?Sized> Clone for Rc<T> {
     fn clone(&self) -> Self {
-        let inner = unsafe { self.inner.as_ref() };
-        let count = inner.refcount.get();
-        inner.refcount.set(count + 1);
+        self.inner().inc_strong();
         Rc {
             inner: self.inner,
             _marker: PhantomData,
@@ -46,15 +189,445 @@ impl<T> Clone for Rc<T> {
     }
 }
 
-impl<T> Drop for Rc<T> {
+impl<T: ?Sized> Drop for Rc<T> {
+    fn drop(&mut self) {
+        let inner = self.inner();
+        let count = inner.strong.get();
+        inner.strong.set(count - 1);
+        if count != 1 {
+            return;
+        }
+        // SAFETY: We were the last strong pointer, so nobody can observe `value`
+        // anymore, and it's dropped only once since `strong` is now zero.
+        unsafe { ManuallyDrop::drop(&mut (*self.inner.as_ptr()).value) };
+        // Release the weak reference held by the strong pointers, this frees
+        // the block if there are no `Weak`s left.
+        drop(Weak {
+            inner: Some(self.inner),
+        });
+    }
+}
+
+impl<T: ?Sized> From<Box<T>> for Rc<T> {
+    fn from(b: Box<T>) -> Self {
+        let ptr = Box::into_raw(b);
+        unsafe {
+            let rc = Rc::copy_from_ptr(ptr);
+            // Free the Box allocation without dropping the value we moved out.
+            drop(Box::from_raw(ptr as *mut ManuallyDrop<T>));
+            rc
+        }
+    }
+}
+
+impl<T> From<Vec<T>> for Rc<[T]> {
+    fn from(mut v: Vec<T>) -> Self {
+        unsafe {
+            let rc = Rc::copy_from_ptr(&*v as *const [T]);
+            // The elements were moved into the block, so the Vec only frees
+            // its buffer.
+            v.set_len(0);
+            rc
+        }
+    }
+}
+
+impl<T: Clone> From<&[T]> for Rc<[T]> {
+    fn from(slice: &[T]) -> Self {
+        Rc::from(slice.to_vec())
+    }
+}
+
+impl From<&str> for Rc<str> {
+    fn from(s: &str) -> Self {
+        let rc = Rc::<[u8]>::from(s.as_bytes());
+        // SAFETY: `str` has the same layout as `[u8]`, and the bytes are UTF-8.
+        unsafe { Rc::from_raw(Rc::into_raw(rc) as *const str) }
+    }
+}
+
+impl From<String> for Rc<str> {
+    fn from(s: String) -> Self {
+        Rc::from(&*s)
+    }
+}
+
+/// Turns an `Rc<T>` into an `Rc<U>` where `T` unsizes to `U`, e.g.
+/// `unsize_rc!(rc, dyn Trait)`. std's `Rc` coerces implicitly, but that needs
+/// the unstable `CoerceUnsized`, so we coerce the raw pointer instead.
+#[macro_export]
+macro_rules! unsize_rc {
+    ($rc:expr, $ty:ty) => {{
+        let ptr = $crate::pointers::rc::Rc::into_raw($rc);
+        // This only compiles for unsizing coercions, so the block has the
+        // layout `from_raw` expects.
+        let ptr: *const $ty = ptr;
+        let rc: $crate::pointers::rc::Rc<$ty> = unsafe { $crate::pointers::rc::Rc::from_raw(ptr) };
+        rc
+    }};
+}
+
+pub struct Weak<T: ?Sized> {
+    // `None` for a `Weak` created by `Weak::new`, which never had a block.
+    inner: Option<NonNull<RcInner<T>>>,
+}
+
+impl<T> Weak<T> {
+    pub fn new() -> Self {
+        Weak { inner: None }
+    }
+}
+
+impl<T: ?Sized> Weak<T> {
+    pub fn upgrade(&self) -> Option<Rc<T>> {
+        let ptr = self.inner?;
+        // SAFETY: See `Weak::inner`.
+        let inner = unsafe { ptr.as_ref() };
+        if inner.is_dead() {
+            return None;
+        }
+        inner.inc_strong();
+        Some(Rc {
+            inner: ptr,
+            _marker: PhantomData,
+        })
+    }
+
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        self.inner.map(|inner| inner.as_ptr() as *const ())
+            == other.inner.map(|inner| inner.as_ptr() as *const ())
+    }
+
+    pub fn strong_count(&self) -> usize {
+        self.inner()
+            .map_or(0, |inner| if inner.is_dead() { 0 } else { inner.strong() })
+    }
+
+    // Like std, 0 once the value is gone, even if other `Weak`s remain.
+    pub fn weak_count(&self) -> usize {
+        self.inner().map_or(0, |inner| {
+            if inner.is_dead() {
+                0
+            } else {
+                inner.weak.get() - 1
+            }
+        })
+    }
+
+    fn inner(&self) -> Option<&RcInner<T>> {
+        // SAFETY: The block is kept alive by our weak reference, although
+        // `value` may already have been dropped.
+        self.inner.map(|inner| unsafe { &*inner.as_ptr() })
+    }
+}
+
+impl<T> Default for Weak<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: ?Sized> Clone for Weak<T> {
+    fn clone(&self) -> Self {
+        if let Some(inner) = self.inner() {
+            inner.inc_weak();
+        }
+        Weak { inner: self.inner }
+    }
+}
+
+impl<T: ?Sized> Drop for Weak<T> {
     fn drop(&mut self) {
-        let inner = unsafe { self.inner.as_ref() };
-        let count = inner.refcount.get();
+        let ptr = match self.inner {
+            Some(ptr) => ptr,
+            None => return,
+        };
+        // SAFETY: See `Weak::inner`.
+        let inner = unsafe { ptr.as_ref() };
+        let count = inner.weak.get();
+        inner.weak.set(count - 1);
         if count == 1 {
-            // drop(inner);
-            let _ = unsafe { Box::from_raw(self.inner.as_ptr()) };
-        } else {
-            inner.refcount.set(count - 1);
+            // SAFETY: The block came from a Box, and we held the last reference
+            // to it. `value` was already dropped by the last `Rc`, and the
+            // `ManuallyDrop` keeps Box from dropping it again.
+            unsafe { drop(Box::from_raw(ptr.as_ptr())) };
         }
     }
 }
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Rc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display> fmt::Display for Rc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized> fmt::Pointer for Rc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Pointer::fmt(&(&**self as *const T), f)
+    }
+}
+
+impl<T: ?Sized + PartialEq> PartialEq for Rc<T> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: ?Sized + Eq> Eq for Rc<T> {}
+
+impl<T: ?Sized + PartialOrd> PartialOrd for Rc<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        (**self).partial_cmp(&**other)
+    }
+}
+
+impl<T: ?Sized + Ord> Ord for Rc<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (**self).cmp(&**other)
+    }
+}
+
+impl<T: ?Sized + Hash> Hash for Rc<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+impl<T: Default> Default for Rc<T> {
+    fn default() -> Self {
+        Rc::new(T::default())
+    }
+}
+
+impl<T> From<T> for Rc<T> {
+    fn from(t: T) -> Self {
+        Rc::new(t)
+    }
+}
+
+impl<T: ?Sized> AsRef<T> for Rc<T> {
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+impl<T: ?Sized> Borrow<T> for Rc<T> {
+    fn borrow(&self) -> &T {
+        self
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for Weak<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(Weak)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pointers::cell::Cell;
+
+    struct DropCounter<'a> {
+        drops: &'a Cell<usize>,
+    }
+
+    impl Drop for DropCounter<'_> {
+        fn drop(&mut self) {
+            self.drops.set(self.drops.get() + 1);
+        }
+    }
+
+    #[test]
+    fn drop_value_with_last_rc() {
+        let drops = Cell::new(0);
+        let a = Rc::new(DropCounter { drops: &drops });
+        let b = a.clone();
+        drop(a);
+        assert_eq!(drops.get(), 0);
+        drop(b);
+        assert_eq!(drops.get(), 1);
+    }
+
+    #[test]
+    fn weak_does_not_keep_value_alive() {
+        let drops = Cell::new(0);
+        let rc = Rc::new(DropCounter { drops: &drops });
+        let weak = Rc::downgrade(&rc);
+        assert_eq!(Rc::strong_count(&rc), 1);
+        assert_eq!(Rc::weak_count(&rc), 1);
+
+        drop(rc);
+        // The value is gone although the block is still held by `weak`.
+        assert_eq!(drops.get(), 1);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(weak.strong_count(), 0);
+        drop(weak);
+        assert_eq!(drops.get(), 1);
+    }
+
+    #[test]
+    fn upgrade_while_alive() {
+        let rc = Rc::new(5);
+        let weak = Rc::downgrade(&rc);
+        let upgraded = weak.upgrade().unwrap();
+        assert_eq!(*upgraded, 5);
+        assert_eq!(Rc::strong_count(&rc), 2);
+        drop(rc);
+        assert_eq!(*weak.upgrade().unwrap(), 5);
+        drop(upgraded);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn weak_outlives_clones() {
+        let drops = Cell::new(0);
+        let rc = Rc::new(DropCounter { drops: &drops });
+        let weak1 = Rc::downgrade(&rc);
+        let weak2 = weak1.clone();
+        assert_eq!(Rc::weak_count(&rc), 2);
+        drop(weak1);
+        assert_eq!(weak2.weak_count(), 1);
+        let weak3 = weak2.clone();
+        drop(rc);
+        assert_eq!(drops.get(), 1);
+        assert!(weak2.upgrade().is_none());
+        // No strong references left, so no weak ones are counted either.
+        assert_eq!((weak2.weak_count(), weak3.weak_count()), (0, 0));
+    }
+
+    #[test]
+    fn dangling_weak() {
+        let weak = Weak::<i32>::new();
+        assert!(weak.upgrade().is_none());
+        assert_eq!(weak.strong_count(), 0);
+        assert_eq!(weak.weak_count(), 0);
+    }
+
+    #[test]
+    fn parent_links() {
+        struct Node<'a> {
+            parent: Weak<Node<'a>>,
+            _drop: DropCounter<'a>,
+        }
+
+        let drops = Cell::new(0);
+        let parent = Rc::new(Node {
+            parent: Weak::new(),
+            _drop: DropCounter { drops: &drops },
+        });
+        let child = Rc::new(Node {
+            parent: Rc::downgrade(&parent),
+            _drop: DropCounter { drops: &drops },
+        });
+        assert!(Rc::ptr_eq(&child.parent.upgrade().unwrap(), &parent));
+
+        // The child's parent link doesn't keep the parent alive.
+        drop(parent);
+        assert_eq!(drops.get(), 1);
+        assert!(child.parent.upgrade().is_none());
+        drop(child);
+        assert_eq!(drops.get(), 2);
+    }
+
+    #[test]
+    fn slices_and_strs() {
+        let drops = Cell::new(0);
+        let rc: Rc<[DropCounter]> = Rc::from(vec![
+            DropCounter { drops: &drops },
+            DropCounter { drops: &drops },
+        ]);
+        assert_eq!(rc.len(), 2);
+        let weak = Rc::downgrade(&rc);
+        drop(rc);
+        assert_eq!(drops.get(), 2);
+        assert!(weak.upgrade().is_none());
+
+        let rc: Rc<[u64]> = Rc::from(&[1, 2, 3][..]);
+        assert_eq!(&*rc, &[1, 2, 3]);
+        let rc: Rc<str> = Rc::from("hello");
+        assert_eq!(&*rc.clone(), "hello");
+        let rc: Rc<str> = Rc::from(String::new());
+        assert_eq!(&*rc, "");
+    }
+
+    #[test]
+    fn from_box() {
+        let drops = Cell::new(0);
+        let b: Box<[DropCounter]> = vec![DropCounter { drops: &drops }].into_boxed_slice();
+        let rc: Rc<[DropCounter]> = Rc::from(b);
+        assert_eq!(drops.get(), 0);
+        drop(rc);
+        assert_eq!(drops.get(), 1);
+
+        let rc: Rc<dyn Fn() -> u8> = Rc::from(Box::new(|| 7u8) as Box<dyn Fn() -> u8>);
+        assert_eq!(rc(), 7);
+    }
+
+    #[test]
+    fn unsize() {
+        trait Speak {
+            fn speak(&self) -> String;
+        }
+        impl Speak for DropCounter<'_> {
+            fn speak(&self) -> String {
+                format!("dropped {} times", self.drops.get())
+            }
+        }
+
+        let drops = Cell::new(0);
+        let rc = Rc::new(DropCounter { drops: &drops });
+        let weak = Rc::downgrade(&rc);
+        let dynamic: Rc<dyn Speak> = crate::unsize_rc!(rc, dyn Speak);
+        assert_eq!(dynamic.speak(), "dropped 0 times");
+        assert!(weak.upgrade().is_some());
+        drop(dynamic);
+        assert_eq!(drops.get(), 1);
+        assert!(weak.upgrade().is_none());
+
+        // Over-aligned values must be found again by `from_raw`.
+        #[repr(align(64))]
+        struct Aligned(u8);
+        impl Speak for Aligned {
+            fn speak(&self) -> String {
+                self.0.to_string()
+            }
+        }
+        let dynamic = crate::unsize_rc!(Rc::new(Aligned(3)), dyn Speak);
+        assert_eq!(dynamic.clone().speak(), "3");
+    }
+
+    #[test]
+    fn as_map_key() {
+        use std::collections::{BTreeMap, HashMap};
+
+        let mut map = HashMap::new();
+        map.insert(Rc::new(String::from("a")), 1);
+        map.insert(Rc::from(String::from("b")), 2);
+        // Equality and hashing go through the pointee, not the pointer.
+        assert_eq!(map[&Rc::new(String::from("a"))], 1);
+        assert_eq!(map.get(&String::from("b")), Some(&2));
+
+        let mut map = BTreeMap::new();
+        map.insert(Rc::<str>::from("b"), 2);
+        map.insert(Rc::<str>::from("a"), 1);
+        assert_eq!(map.keys().map(|k| &**k).collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(map.get("a"), Some(&1));
+    }
+
+    #[test]
+    fn formatting() {
+        let value = Rc::new(5);
+        assert_eq!(format!("{:?} {}", value, value), "5 5");
+        assert_eq!(format!("{:p}", value), format!("{:p}", &*value));
+        assert_eq!(format!("{:?}", Rc::downgrade(&value)), "(Weak)");
+        assert_eq!(Rc::<Vec<u8>>::default().len(), 0);
+        assert!(Rc::new(1) < Rc::new(2));
+    }
+}
@@ -0,0 +1,564 @@
+// A synchronous trial deletion cycle collector, after Bacon and Rajan's
+// "Concurrent Cycle Collection in Reference Counted Systems".
+//
+// Reference counting can't free a cycle, since every node in it keeps the next
+// one alive. But if we subtract the references coming from inside a subgraph,
+// whatever still has a count left is referenced from outside, and everything
+// it reaches is alive. What's left is garbage, and it's only referenced by
+// itself.
+//
+// Unlike the paper we keep the colors and trial counts in a table on the side,
+// so `RcInner` doesn't grow for values that never take part in collection.
+use super::{Rc, RcInner, Weak, COLLECTING};
+use crate::pointers::ref_cell::RefCell;
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    marker::PhantomData,
+    mem::ManuallyDrop,
+    ptr::NonNull,
+};
+
+/// Types that can tell the collector about the `Rc`s they own.
+///
+/// # Safety
+///
+/// `collect_cycles` frees values based on what `trace` reports, so:
+///
+/// - `trace` must visit `Rc`s directly owned by `self` only, each at most
+///   once. Visiting an `Rc` that isn't owned by `self`, or one twice, frees
+///   values that are still in use. Missing one only makes the collector keep
+///   more alive than necessary.
+/// - `trace` must report the same `Rc`s every time it's called during a
+///   collection, and must not create, clone or drop any.
+/// - The `Drop` impl of a traced type must not dereference its `Rc`s, since the
+///   collector drops the values of a cycle in no particular order, and must
+///   not move or clone its `Rc`s somewhere else to keep them alive. Upgrading a
+///   `Weak` to a value being collected fails.
+pub unsafe trait Trace {
+    fn trace(&self, tracer: &mut Tracer<'_>);
+}
+
+pub struct Tracer<'a> {
+    visit: &'a mut dyn FnMut(Node),
+}
+
+impl Tracer<'_> {
+    pub fn visit<T: Trace + 'static>(&mut self, rc: &Rc<T>) {
+        (self.visit)(Node::new(rc.inner));
+    }
+}
+
+// The type erased operations on an `RcInner<T: Trace>`. It's implemented by a
+// zero sized type, so a `&'static dyn NodeOps` is just a vtable, and we pass the
+// pointer to the block separately to keep its provenance.
+trait NodeOps {
+    unsafe fn strong(&self, ptr: NonNull<u8>) -> usize;
+    unsafe fn trace(&self, ptr: NonNull<u8>, visit: &mut dyn FnMut(Node));
+    unsafe fn start_collecting(&self, ptr: NonNull<u8>);
+    unsafe fn inc_weak(&self, ptr: NonNull<u8>);
+    unsafe fn drop_weak(&self, ptr: NonNull<u8>);
+    unsafe fn drop_value(&self, ptr: NonNull<u8>);
+    unsafe fn release(&self, ptr: NonNull<u8>);
+}
+
+struct Ops<T>(PhantomData<T>);
+
+impl<T: Trace> Ops<T> {
+    unsafe fn inner<'a>(ptr: NonNull<u8>) -> &'a RcInner<T> {
+        ptr.cast::<RcInner<T>>().as_ref()
+    }
+}
+
+impl<T: Trace> NodeOps for Ops<T> {
+    unsafe fn strong(&self, ptr: NonNull<u8>) -> usize {
+        Self::inner(ptr).strong.get()
+    }
+
+    unsafe fn trace(&self, ptr: NonNull<u8>, visit: &mut dyn FnMut(Node)) {
+        Self::inner(ptr).value.trace(&mut Tracer { visit });
+    }
+
+    unsafe fn start_collecting(&self, ptr: NonNull<u8>) {
+        let strong = &Self::inner(ptr).strong;
+        strong.set(strong.get() + COLLECTING);
+    }
+
+    unsafe fn inc_weak(&self, ptr: NonNull<u8>) {
+        Self::inner(ptr).inc_weak();
+    }
+
+    unsafe fn drop_weak(&self, ptr: NonNull<u8>) {
+        drop(Weak {
+            inner: Some(ptr.cast::<RcInner<T>>()),
+        });
+    }
+
+    unsafe fn drop_value(&self, ptr: NonNull<u8>) {
+        ManuallyDrop::drop(&mut (*ptr.cast::<RcInner<T>>().as_ptr()).value);
+    }
+
+    // Like the last `Rc::drop`, without dropping the value again.
+    unsafe fn release(&self, ptr: NonNull<u8>) {
+        let strong = &Self::inner(ptr).strong;
+        // Every `Rc` to a garbage value is owned by another one, and those are
+        // all dropped by now. If one escaped anyway, leak the block rather than
+        // free memory it still points to.
+        if strong.get() != COLLECTING {
+            return;
+        }
+        strong.set(0);
+        self.drop_weak(ptr);
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Node {
+    ptr: NonNull<u8>,
+    ops: &'static dyn NodeOps,
+}
+
+impl Node {
+    fn new<T: Trace + 'static>(inner: NonNull<RcInner<T>>) -> Self {
+        Node {
+            ptr: inner.cast(),
+            ops: &Ops::<T>(PhantomData),
+        }
+    }
+
+    // All of the below are fine to call while the block is alive, which is
+    // guaranteed by the weak reference held by the roots buffer, or by the
+    // strong reference of the value that traced us.
+    fn strong(self) -> usize {
+        unsafe { self.ops.strong(self.ptr) }
+    }
+
+    fn children(self) -> Vec<Node> {
+        let mut children = Vec::new();
+        unsafe { self.ops.trace(self.ptr, &mut |node| children.push(node)) };
+        children
+    }
+}
+
+thread_local! {
+    // The possible roots of garbage cycles. Each one holds a weak reference,
+    // so the block stays around even if the value is dropped in the meantime.
+    static ROOTS: std::cell::RefCell<HashMap<NonNull<u8>, Node>> = Default::default();
+}
+
+/// Registers `rc` as a possible root of a cycle, to be looked at by the next
+/// `collect_cycles` on this thread.
+pub fn register_root<T: Trace + 'static>(rc: &Rc<T>) {
+    let node = Node::new(rc.inner);
+    ROOTS.with(|roots| {
+        if let Entry::Vacant(entry) = roots.borrow_mut().entry(node.ptr) {
+            unsafe { node.ops.inc_weak(node.ptr) };
+            entry.insert(node);
+        }
+    });
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Color {
+    // In use, or not looked at yet.
+    Black,
+    // Possible member of a garbage cycle.
+    Gray,
+    // Member of a garbage cycle.
+    White,
+}
+
+struct Collector {
+    // The color and the trial count of every node we've seen so far.
+    state: HashMap<NonNull<u8>, (Color, usize)>,
+}
+
+impl Collector {
+    fn entry(&mut self, node: Node) -> &mut (Color, usize) {
+        self.state
+            .entry(node.ptr)
+            .or_insert_with(|| (Color::Black, node.strong()))
+    }
+
+    // Subtracts the references from inside the subgraph reachable from `root`.
+    fn mark_gray(&mut self, root: Node) {
+        let mut stack = vec![root];
+        while let Some(node) = stack.pop() {
+            let (color, _) = self.entry(node);
+            if *color == Color::Gray {
+                continue;
+            }
+            *color = Color::Gray;
+            for child in node.children() {
+                self.entry(child).1 -= 1;
+                stack.push(child);
+            }
+        }
+    }
+
+    // Whatever still has a count is referenced from outside, so it and
+    // everything it reaches is alive. The rest is garbage, for now.
+    fn scan(&mut self, root: Node) {
+        let mut stack = vec![root];
+        while let Some(node) = stack.pop() {
+            let (color, count) = self.entry(node);
+            if *color != Color::Gray {
+                continue;
+            }
+            if *count > 0 {
+                self.scan_black(node);
+            } else {
+                *color = Color::White;
+                stack.extend(node.children());
+            }
+        }
+    }
+
+    // Restores the counts of everything reachable from a node that is alive.
+    fn scan_black(&mut self, root: Node) {
+        self.entry(root).0 = Color::Black;
+        let mut stack = vec![root];
+        while let Some(node) = stack.pop() {
+            for child in node.children() {
+                let (color, count) = self.entry(child);
+                *count += 1;
+                if *color != Color::Black {
+                    *color = Color::Black;
+                    stack.push(child);
+                }
+            }
+        }
+    }
+
+    fn collect_white(&mut self, root: Node, garbage: &mut Vec<Node>) {
+        let mut stack = vec![root];
+        while let Some(node) = stack.pop() {
+            let (color, _) = self.entry(node);
+            if *color != Color::White {
+                continue;
+            }
+            *color = Color::Black;
+            garbage.push(node);
+            stack.extend(node.children());
+        }
+    }
+}
+
+/// Frees the garbage cycles reachable from the roots registered on this thread,
+/// and forgets about the roots. Returns the number of freed values.
+pub fn collect_cycles() -> usize {
+    let roots: Vec<Node> = ROOTS.with(|roots| roots.borrow_mut().drain().map(|(_, n)| n).collect());
+    // A root whose value was dropped already is no cycle, and its value can't
+    // be traced anymore.
+    let live_roots: Vec<Node> = roots.iter().copied().filter(|n| n.strong() > 0).collect();
+
+    let mut collector = Collector {
+        state: HashMap::new(),
+    };
+    for &root in &live_roots {
+        collector.mark_gray(root);
+    }
+    for &root in &live_roots {
+        collector.scan(root);
+    }
+    let mut garbage = Vec::new();
+    for &root in &live_roots {
+        collector.collect_white(root, &mut garbage);
+    }
+
+    unsafe {
+        // Mark every member of the garbage as dead before running any
+        // destructor, so none of them can be upgraded to, and dropping the
+        // values doesn't drop or free any of them while we're at it.
+        for node in &garbage {
+            node.ops.start_collecting(node.ptr);
+        }
+        // Dropping the values drops the `Rc`s between them, so once all values
+        // are gone each count is back at `COLLECTING`.
+        for node in &garbage {
+            node.ops.drop_value(node.ptr);
+        }
+        for node in &garbage {
+            node.ops.release(node.ptr);
+        }
+        for root in roots {
+            root.ops.drop_weak(root.ptr);
+        }
+    }
+    garbage.len()
+}
+
+unsafe impl<T: Trace + 'static> Trace for Rc<T> {
+    fn trace(&self, tracer: &mut Tracer<'_>) {
+        tracer.visit(self);
+    }
+}
+
+// A `Weak` doesn't keep anything alive, so it's no edge of the graph.
+unsafe impl<T: ?Sized> Trace for Weak<T> {
+    fn trace(&self, _: &mut Tracer<'_>) {}
+}
+
+unsafe impl<T: Trace> Trace for RefCell<T> {
+    fn trace(&self, tracer: &mut Tracer<'_>) {
+        // If it's mutably borrowed we can't look inside, so its `Rc`s look
+        // like they're referenced from outside and are kept alive.
+        if let Ok(value) = self.try_borrow() {
+            value.trace(tracer);
+        }
+    }
+}
+
+unsafe impl<T: Trace> Trace for Option<T> {
+    fn trace(&self, tracer: &mut Tracer<'_>) {
+        if let Some(value) = self {
+            value.trace(tracer);
+        }
+    }
+}
+
+unsafe impl<T: Trace> Trace for Vec<T> {
+    fn trace(&self, tracer: &mut Tracer<'_>) {
+        for value in self {
+            value.trace(tracer);
+        }
+    }
+}
+
+unsafe impl<T: Trace + ?Sized> Trace for Box<T> {
+    fn trace(&self, tracer: &mut Tracer<'_>) {
+        (**self).trace(tracer);
+    }
+}
+
+macro_rules! impl_trace_for_leaves {
+    ($($t:ty),*) => {
+        $(unsafe impl Trace for $t {
+            fn trace(&self, _: &mut Tracer<'_>) {}
+        })*
+    };
+}
+
+impl_trace_for_leaves!(
+    (),
+    bool,
+    char,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    f32,
+    f64,
+    String,
+    &'static str
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pointers::cell::Cell;
+
+    struct DropCounter {
+        drops: Rc<Cell<usize>>,
+    }
+
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.drops.set(self.drops.get() + 1);
+        }
+    }
+
+    struct Node {
+        edges: RefCell<Vec<Rc<Node>>>,
+        _drop: DropCounter,
+    }
+
+    // SAFETY: Visits the `Rc`s in `edges`, which are all it owns.
+    unsafe impl Trace for Node {
+        fn trace(&self, tracer: &mut Tracer<'_>) {
+            self.edges.trace(tracer);
+        }
+    }
+
+    fn node(drops: &Rc<Cell<usize>>) -> Rc<Node> {
+        Rc::new(Node {
+            edges: RefCell::new(Vec::new()),
+            _drop: DropCounter {
+                drops: drops.clone(),
+            },
+        })
+    }
+
+    fn link(from: &Rc<Node>, to: &Rc<Node>) {
+        from.edges.borrow_mut().push(to.clone());
+    }
+
+    #[test]
+    fn frees_cycle() {
+        let drops = Rc::new(Cell::new(0));
+        let a = node(&drops);
+        let b = node(&drops);
+        link(&a, &b);
+        link(&b, &a);
+        let weak = Rc::downgrade(&a);
+        register_root(&a);
+        drop(a);
+        drop(b);
+        // Leaked, as far as reference counting is concerned.
+        assert_eq!(drops.get(), 0);
+
+        assert_eq!(collect_cycles(), 2);
+        assert_eq!(drops.get(), 2);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn frees_self_cycle() {
+        let drops = Rc::new(Cell::new(0));
+        let a = node(&drops);
+        link(&a, &a);
+        register_root(&a);
+        drop(a);
+        assert_eq!(collect_cycles(), 1);
+        assert_eq!(drops.get(), 1);
+    }
+
+    #[test]
+    fn keeps_referenced_cycle() {
+        let drops = Rc::new(Cell::new(0));
+        let a = node(&drops);
+        let b = node(&drops);
+        let c = node(&drops);
+        link(&a, &b);
+        link(&b, &c);
+        link(&c, &a);
+        register_root(&a);
+        drop(a);
+        drop(b);
+
+        // `c` is still referenced from outside, so the whole cycle is alive.
+        assert_eq!(collect_cycles(), 0);
+        assert_eq!(drops.get(), 0);
+        assert_eq!(c.edges.borrow().len(), 1);
+
+        register_root(&c);
+        drop(c);
+        assert_eq!(collect_cycles(), 3);
+        assert_eq!(drops.get(), 3);
+    }
+
+    #[test]
+    fn keeps_values_reachable_from_garbage() {
+        let drops = Rc::new(Cell::new(0));
+        let a = node(&drops);
+        let b = node(&drops);
+        let live = node(&drops);
+        link(&a, &b);
+        link(&b, &a);
+        link(&a, &live);
+        register_root(&a);
+        register_root(&live);
+        drop((a, b));
+
+        assert_eq!(collect_cycles(), 2);
+        assert_eq!(drops.get(), 2);
+        assert_eq!(Rc::strong_count(&live), 1);
+        drop(live);
+        assert_eq!(drops.get(), 3);
+    }
+
+    #[test]
+    fn roots_that_are_gone_or_acyclic() {
+        let drops = Rc::new(Cell::new(0));
+        let a = node(&drops);
+        let b = node(&drops);
+        link(&a, &b);
+        register_root(&a);
+        register_root(&a);
+        register_root(&b);
+        drop(a);
+        // `a` was freed by reference counting while registered.
+        assert_eq!(drops.get(), 1);
+
+        assert_eq!(collect_cycles(), 0);
+        assert_eq!(Rc::strong_count(&b), 1);
+        drop(b);
+        assert_eq!(drops.get(), 2);
+    }
+
+    #[test]
+    fn long_chains() {
+        let drops = Rc::new(Cell::new(0));
+        let first = node(&drops);
+        let mut last = first.clone();
+        for _ in 0..100_000 {
+            let next = node(&drops);
+            link(&last, &next);
+            last = next;
+        }
+        link(&last, &first);
+        register_root(&first);
+        drop((first, last));
+        assert_eq!(collect_cycles(), 100_001);
+        assert_eq!(drops.get(), 100_001);
+    }
+
+    #[test]
+    fn no_upgrades_while_collecting() {
+        struct Upgrader {
+            next: RefCell<Option<Rc<Upgrader>>>,
+            prev: RefCell<Weak<Upgrader>>,
+            upgrades: Rc<Cell<usize>>,
+            strong_counts: Rc<Cell<usize>>,
+        }
+
+        // SAFETY: `next` is the only `Rc<Upgrader>` it owns.
+        unsafe impl Trace for Upgrader {
+            fn trace(&self, tracer: &mut Tracer<'_>) {
+                self.next.trace(tracer);
+            }
+        }
+
+        impl Drop for Upgrader {
+            fn drop(&mut self) {
+                let prev = self.prev.borrow();
+                if prev.upgrade().is_some() {
+                    self.upgrades.set(self.upgrades.get() + 1);
+                }
+                let counts = self.strong_counts.get();
+                self.strong_counts.set(counts + prev.strong_count());
+            }
+        }
+
+        let upgrades = Rc::new(Cell::new(0));
+        let strong_counts = Rc::new(Cell::new(0));
+        let nodes: Vec<_> = (0..3)
+            .map(|_| {
+                Rc::new(Upgrader {
+                    next: RefCell::new(None),
+                    prev: RefCell::new(Weak::new()),
+                    upgrades: upgrades.clone(),
+                    strong_counts: strong_counts.clone(),
+                })
+            })
+            .collect();
+        for i in 0..3 {
+            let next = &nodes[(i + 1) % 3];
+            *nodes[i].next.borrow_mut() = Some(next.clone());
+            *next.prev.borrow_mut() = Rc::downgrade(&nodes[i]);
+        }
+        register_root(&nodes[0]);
+        drop(nodes);
+
+        // Whichever value is dropped first, the others are already dead.
+        assert_eq!(collect_cycles(), 3);
+        assert_eq!((upgrades.get(), strong_counts.get()), (0, 0));
+    }
+}
@@ -1,79 +1,308 @@
 use super::cell::Cell;
 use std::{
     cell::UnsafeCell,
+    error::Error,
+    fmt,
+    marker::PhantomData,
+    mem,
     ops::{Deref, DerefMut},
+    ptr::NonNull,
 };
 
+#[cfg(feature = "track-borrows")]
+use std::panic::Location;
+
 #[derive(Copy, Clone)]
 enum RefCellState {
     Unshared,
     Shared(usize),
-    Exclusive,
+    // More than one `RefMut` only after `RefMut::map_split`, and then each
+    // of them points at a disjoint part of the value.
+    Exclusive(usize),
 }
 
-pub struct Ref<'refcell, T> {
-    refcell: &'refcell RefCell<T>,
+// A shared borrow of a `RefCell`, which is given back when it's dropped. It's
+// separate from `Ref` so a `Ref` can point at a part of the value, see `Ref::map`.
+struct BorrowRef<'b> {
+    state: &'b Cell<RefCellState>,
+    #[cfg(feature = "track-borrows")]
+    borrows: &'b BorrowLocations,
+    #[cfg(feature = "track-borrows")]
+    location: &'static Location<'static>,
 }
 
-impl<T> Drop for Ref<'_, T> {
+impl<'b> BorrowRef<'b> {
+    #[track_caller]
+    fn new<T>(refcell: &'b RefCell<T>) -> Option<Self> {
+        match refcell.state.get() {
+            RefCellState::Unshared => refcell.state.set(RefCellState::Shared(1)),
+            RefCellState::Shared(n) => refcell.state.set(RefCellState::Shared(n + 1)),
+            RefCellState::Exclusive(_) => return None,
+        }
+        Some(BorrowRef {
+            state: &refcell.state,
+            #[cfg(feature = "track-borrows")]
+            borrows: &refcell.borrows,
+            #[cfg(feature = "track-borrows")]
+            location: refcell.borrows.add(Location::caller()),
+        })
+    }
+
+    #[track_caller]
+    fn clone(&self) -> Self {
+        match self.state.get() {
+            RefCellState::Shared(n) => self.state.set(RefCellState::Shared(n + 1)),
+            RefCellState::Exclusive(_) | RefCellState::Unshared => unreachable!(),
+        }
+        BorrowRef {
+            state: self.state,
+            #[cfg(feature = "track-borrows")]
+            borrows: self.borrows,
+            #[cfg(feature = "track-borrows")]
+            location: self.borrows.add(Location::caller()),
+        }
+    }
+}
+
+impl Drop for BorrowRef<'_> {
     fn drop(&mut self) {
-        match self.refcell.state.get() {
-            RefCellState::Exclusive | RefCellState::Unshared => unreachable!(),
+        #[cfg(feature = "track-borrows")]
+        self.borrows.remove(self.location);
+        match self.state.get() {
+            RefCellState::Exclusive(_) | RefCellState::Unshared => unreachable!(),
             RefCellState::Shared(1) => {
-                self.refcell.state.set(RefCellState::Unshared);
+                self.state.set(RefCellState::Unshared);
             }
             RefCellState::Shared(n) => {
-                self.refcell.state.set(RefCellState::Shared(n - 1));
+                self.state.set(RefCellState::Shared(n - 1));
             }
         }
     }
 }
 
-impl<T> Deref for Ref<'_, T> {
+// An exclusive borrow of a `RefCell`, see `BorrowRef`.
+struct BorrowRefMut<'b> {
+    state: &'b Cell<RefCellState>,
+    #[cfg(feature = "track-borrows")]
+    borrows: &'b BorrowLocations,
+    #[cfg(feature = "track-borrows")]
+    location: &'static Location<'static>,
+}
+
+impl<'b> BorrowRefMut<'b> {
+    #[track_caller]
+    fn new<T>(refcell: &'b RefCell<T>) -> Option<Self> {
+        if let RefCellState::Unshared = refcell.state.get() {
+            refcell.state.set(RefCellState::Exclusive(1));
+            Some(BorrowRefMut {
+                state: &refcell.state,
+                #[cfg(feature = "track-borrows")]
+                borrows: &refcell.borrows,
+                #[cfg(feature = "track-borrows")]
+                location: refcell.borrows.add(Location::caller()),
+            })
+        } else {
+            None
+        }
+    }
+
+    // Only for `RefMut::map_split`, where the two halves don't overlap. Both
+    // halves report the location of the original borrow.
+    fn split(&self) -> Self {
+        match self.state.get() {
+            RefCellState::Exclusive(n) => self.state.set(RefCellState::Exclusive(n + 1)),
+            RefCellState::Shared(_) | RefCellState::Unshared => unreachable!(),
+        }
+        BorrowRefMut {
+            state: self.state,
+            #[cfg(feature = "track-borrows")]
+            borrows: self.borrows,
+            #[cfg(feature = "track-borrows")]
+            location: self.borrows.add(self.location),
+        }
+    }
+}
+
+impl Drop for BorrowRefMut<'_> {
+    fn drop(&mut self) {
+        #[cfg(feature = "track-borrows")]
+        self.borrows.remove(self.location);
+        match self.state.get() {
+            RefCellState::Shared(_) | RefCellState::Unshared => unreachable!(),
+            RefCellState::Exclusive(1) => {
+                self.state.set(RefCellState::Unshared);
+            }
+            RefCellState::Exclusive(n) => {
+                self.state.set(RefCellState::Exclusive(n - 1));
+            }
+        }
+    }
+}
+
+pub struct Ref<'b, T: ?Sized> {
+    // Points into the value of the RefCell borrowed by `borrow`.
+    value: NonNull<T>,
+    borrow: BorrowRef<'b>,
+}
+
+impl<'b, T: ?Sized> Ref<'b, T> {
+    // Not `Clone`, so `r.clone()` keeps cloning the value behind the `Ref`.
+    #[allow(clippy::should_implement_trait)]
+    #[track_caller]
+    pub fn clone(orig: &Ref<'b, T>) -> Ref<'b, T> {
+        Ref {
+            value: orig.value,
+            borrow: orig.borrow.clone(),
+        }
+    }
+
+    pub fn map<U: ?Sized>(orig: Ref<'b, T>, f: impl FnOnce(&T) -> &U) -> Ref<'b, U> {
+        Ref {
+            value: NonNull::from(f(&*orig)),
+            borrow: orig.borrow,
+        }
+    }
+
+    pub fn filter_map<U: ?Sized>(
+        orig: Ref<'b, T>,
+        f: impl FnOnce(&T) -> Option<&U>,
+    ) -> Result<Ref<'b, U>, Self> {
+        match f(&*orig) {
+            Some(value) => Ok(Ref {
+                value: NonNull::from(value),
+                borrow: orig.borrow,
+            }),
+            None => Err(orig),
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for Ref<'_, T> {
     type Target = T;
     fn deref(&self) -> &Self::Target {
         // SAFETY: A Ref is only created if no exclusive references have been given out,
         // once it is given out, state is set to Shared, so no exclusive references are given out,
         // so dereferencing into a shared reference is fine.
-        unsafe { &*self.refcell.value.get() }
+        unsafe { self.value.as_ref() }
     }
 }
 
-pub struct RefMut<'refcell, T> {
-    refcell: &'refcell RefCell<T>,
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Ref<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
 }
 
-impl<T> Drop for RefMut<'_, T> {
-    fn drop(&mut self) {
-        match self.refcell.state.get() {
-            RefCellState::Shared(_) | RefCellState::Unshared => unreachable!(),
-            RefCellState::Exclusive => {
-                self.refcell.state.set(RefCellState::Unshared);
-            }
+pub struct RefMut<'b, T: ?Sized> {
+    // Points into the value of the RefCell borrowed by `borrow`.
+    value: NonNull<T>,
+    borrow: BorrowRefMut<'b>,
+    // `NonNull` is covariant, but a `RefMut` must be invariant like `&mut T`.
+    _marker: PhantomData<&'b mut T>,
+}
+
+impl<'b, T: ?Sized> RefMut<'b, T> {
+    pub fn map<U: ?Sized>(
+        mut orig: RefMut<'b, T>,
+        f: impl FnOnce(&mut T) -> &mut U,
+    ) -> RefMut<'b, U> {
+        RefMut {
+            value: NonNull::from(f(&mut *orig)),
+            borrow: orig.borrow,
+            _marker: PhantomData,
         }
     }
+
+    pub fn map_split<U: ?Sized, V: ?Sized>(
+        mut orig: RefMut<'b, T>,
+        f: impl FnOnce(&mut T) -> (&mut U, &mut V),
+    ) -> (RefMut<'b, U>, RefMut<'b, V>) {
+        let (a, b) = f(&mut *orig);
+        let (a, b) = (NonNull::from(a), NonNull::from(b));
+        let borrow = orig.borrow.split();
+        (
+            RefMut {
+                value: a,
+                borrow,
+                _marker: PhantomData,
+            },
+            RefMut {
+                value: b,
+                borrow: orig.borrow,
+                _marker: PhantomData,
+            },
+        )
+    }
 }
 
-impl<T> Deref for RefMut<'_, T> {
+impl<T: ?Sized> Deref for RefMut<'_, T> {
     type Target = T;
     fn deref(&self) -> &Self::Target {
         // SAFETY: See SAFETY for DerefMut.
-        unsafe { &*self.refcell.value.get() }
+        unsafe { self.value.as_ref() }
     }
 }
 
-impl<T> DerefMut for RefMut<'_, T> {
+impl<T: ?Sized> DerefMut for RefMut<'_, T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         // SAFETY: A RefMut is only created if no other references have been given out,
         // once it is given out, state is set to Exclusive, so no future references are given out,
         // so we have an exclusive reference on the inner value, so mutably dereferencing is fine.
-        unsafe { &mut *self.refcell.value.get() }
+        // After `map_split` the RefMuts point at disjoint parts of the value.
+        unsafe { self.value.as_mut() }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RefMut<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
     }
 }
 
 pub struct RefCell<T> {
     value: UnsafeCell<T>,
     state: Cell<RefCellState>,
+    // Where the outstanding `Ref`s or `RefMut` were created, for the errors.
+    // This lives next to `state` rather than in it, so the state machine is
+    // the same with and without the feature.
+    #[cfg(feature = "track-borrows")]
+    borrows: BorrowLocations,
+}
+
+#[cfg(feature = "track-borrows")]
+struct BorrowLocations(UnsafeCell<Vec<&'static Location<'static>>>);
+
+#[cfg(feature = "track-borrows")]
+impl BorrowLocations {
+    // SAFETY (for all of the below): `RefCell` is `!Sync`, and we never hand out
+    // references into the `Vec`, so nobody else is looking at it.
+    fn add(&self, location: &'static Location<'static>) -> &'static Location<'static> {
+        unsafe { (*self.0.get()).push(location) };
+        location
+    }
+
+    fn remove(&self, location: &'static Location<'static>) {
+        let locations = unsafe { &mut *self.0.get() };
+        if let Some(i) = locations.iter().position(|&l| l == location) {
+            locations.swap_remove(i);
+        }
+    }
+
+    fn get(&self) -> Vec<&'static Location<'static>> {
+        unsafe { (*self.0.get()).clone() }
+    }
+}
+
+#[cfg(feature = "track-borrows")]
+fn fmt_locations(
+    locations: &[&'static Location<'static>],
+    f: &mut fmt::Formatter<'_>,
+) -> fmt::Result {
+    for (i, location) in locations.iter().enumerate() {
+        let sep = if i == 0 { ", borrowed at " } else { ", " };
+        write!(f, "{}{}", sep, location)?;
+    }
+    Ok(())
 }
 
 impl<T> RefCell<T> {
@@ -81,29 +310,261 @@ impl<T> RefCell<T> {
         Self {
             value: UnsafeCell::new(value),
             state: Cell::new(RefCellState::Unshared),
+            #[cfg(feature = "track-borrows")]
+            borrows: BorrowLocations(UnsafeCell::new(Vec::new())),
         }
     }
 
-    pub fn borrow(&self) -> Option<Ref<'_, T>> {
-        match self.state.get() {
-            RefCellState::Unshared => {
-                self.state.set(RefCellState::Shared(1));
-                Some(Ref { refcell: self })
-            }
-            RefCellState::Shared(n) => {
-                self.state.set(RefCellState::Shared(n + 1));
-                Some(Ref { refcell: self })
-            }
-            RefCellState::Exclusive => None,
+    // SAFETY: `UnsafeCell::get` never gives us a null pointer.
+    fn value_ptr(&self) -> NonNull<T> {
+        unsafe { NonNull::new_unchecked(self.value.get()) }
+    }
+
+    #[track_caller]
+    pub fn try_borrow(&self) -> Result<Ref<'_, T>, BorrowError> {
+        match BorrowRef::new(self) {
+            Some(borrow) => Ok(Ref {
+                value: self.value_ptr(),
+                borrow,
+            }),
+            None => Err(BorrowError {
+                #[cfg(feature = "track-borrows")]
+                borrowed_at: self.borrows.get(),
+                _private: (),
+            }),
         }
     }
 
-    pub fn borrow_mut(&self) -> Option<RefMut<'_, T>> {
-        if let RefCellState::Unshared = self.state.get() {
-            self.state.set(RefCellState::Exclusive);
-            Some(RefMut { refcell: self })
-        } else {
-            None
+    #[track_caller]
+    pub fn try_borrow_mut(&self) -> Result<RefMut<'_, T>, BorrowMutError> {
+        match BorrowRefMut::new(self) {
+            Some(borrow) => Ok(RefMut {
+                value: self.value_ptr(),
+                borrow,
+                _marker: PhantomData,
+            }),
+            None => Err(BorrowMutError {
+                #[cfg(feature = "track-borrows")]
+                borrowed_at: self.borrows.get(),
+                _private: (),
+            }),
+        }
+    }
+
+    #[track_caller]
+    pub fn borrow(&self) -> Ref<'_, T> {
+        match self.try_borrow() {
+            Ok(r) => r,
+            Err(err) => panic!("{}", err),
         }
     }
+
+    #[track_caller]
+    pub fn borrow_mut(&self) -> RefMut<'_, T> {
+        match self.try_borrow_mut() {
+            Ok(r) => r,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    #[track_caller]
+    pub fn replace(&self, t: T) -> T {
+        mem::replace(&mut *self.borrow_mut(), t)
+    }
+
+    #[track_caller]
+    pub fn replace_with(&self, f: impl FnOnce(&mut T) -> T) -> T {
+        let mut value = self.borrow_mut();
+        let new = f(&mut *value);
+        mem::replace(&mut *value, new)
+    }
+
+    #[track_caller]
+    pub fn swap(&self, other: &Self) {
+        mem::swap(&mut *self.borrow_mut(), &mut *other.borrow_mut())
+    }
+
+    #[track_caller]
+    pub fn take(&self) -> T
+    where
+        T: Default,
+    {
+        self.replace(T::default())
+    }
+
+    pub fn into_inner(self) -> T {
+        // We own the RefCell, so there can't be any outstanding borrows.
+        self.value.into_inner()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+#[derive(Debug)]
+pub struct BorrowError {
+    // Where the conflicting `RefMut` was created.
+    #[cfg(feature = "track-borrows")]
+    borrowed_at: Vec<&'static Location<'static>>,
+    _private: (),
+}
+
+impl fmt::Display for BorrowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("already mutably borrowed")?;
+        #[cfg(feature = "track-borrows")]
+        fmt_locations(&self.borrowed_at, f)?;
+        Ok(())
+    }
+}
+
+impl Error for BorrowError {}
+
+#[derive(Debug)]
+pub struct BorrowMutError {
+    // Where the conflicting `Ref`s or `RefMut` were created.
+    #[cfg(feature = "track-borrows")]
+    borrowed_at: Vec<&'static Location<'static>>,
+    _private: (),
+}
+
+impl fmt::Display for BorrowMutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("already borrowed")?;
+        #[cfg(feature = "track-borrows")]
+        fmt_locations(&self.borrowed_at, f)?;
+        Ok(())
+    }
+}
+
+impl Error for BorrowMutError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn borrows() {
+        let cell = RefCell::new(vec![1]);
+        {
+            let a = cell.borrow();
+            let b = cell.borrow();
+            assert_eq!(a.len() + b.len(), 2);
+            assert!(cell.try_borrow_mut().is_err());
+        }
+        cell.borrow_mut().push(2);
+        let mut guard = cell.borrow_mut();
+        guard.push(3);
+        assert!(cell
+            .try_borrow()
+            .unwrap_err()
+            .to_string()
+            .starts_with("already mutably borrowed"));
+        drop(guard);
+        assert_eq!(cell.into_inner(), [1, 2, 3]);
+    }
+
+    #[test]
+    #[should_panic(expected = "already borrowed")]
+    fn borrow_mut_while_shared() {
+        let cell = RefCell::new(1);
+        let _a = cell.borrow();
+        let _b = cell.borrow_mut();
+    }
+
+    #[test]
+    #[should_panic(expected = "already mutably borrowed")]
+    fn borrow_while_exclusive() {
+        let cell = RefCell::new(1);
+        let _a = cell.borrow_mut();
+        let _b = cell.borrow();
+    }
+
+    #[test]
+    fn replace_swap_take() {
+        let a = RefCell::new(String::from("a"));
+        let b = RefCell::new(String::from("b"));
+        assert_eq!(a.replace(String::from("c")), "a");
+        assert_eq!(a.replace_with(|old| old.clone() + "d"), "c");
+        a.swap(&b);
+        assert_eq!(b.take(), "cd");
+        assert_eq!(*a.borrow(), "b");
+        assert_eq!(b.into_inner(), "");
+    }
+
+    #[test]
+    #[should_panic(expected = "already borrowed")]
+    fn swap_with_itself() {
+        let a = RefCell::new(1);
+        a.swap(&a);
+    }
+
+    #[test]
+    fn map_fields() {
+        struct Pair {
+            name: String,
+            values: Vec<i32>,
+        }
+
+        let cell = RefCell::new(Pair {
+            name: String::from("pair"),
+            values: vec![1, 2],
+        });
+        let name = Ref::map(cell.borrow(), |pair| pair.name.as_str());
+        let name2 = Ref::clone(&name);
+        let first = Ref::filter_map(cell.borrow(), |pair| pair.values.first())
+            .ok()
+            .unwrap();
+        assert!(Ref::filter_map(cell.borrow(), |pair| pair.values.get(5)).is_err());
+        assert_eq!((&*name, &*name2, *first), ("pair", "pair", 1));
+        assert!(cell.try_borrow_mut().is_err());
+        drop((name, first));
+        assert!(cell.try_borrow_mut().is_err());
+        drop(name2);
+
+        let mut values = RefMut::map(cell.borrow_mut(), |pair| &mut pair.values);
+        values.push(3);
+        assert!(cell.try_borrow().is_err());
+        drop(values);
+        assert_eq!(cell.borrow().values, [1, 2, 3]);
+    }
+
+    #[test]
+    fn map_split() {
+        let cell = RefCell::new([1, 2, 3, 4]);
+        let (mut front, mut back) = RefMut::map_split(cell.borrow_mut(), |v| v.split_at_mut(2));
+        front[0] = 10;
+        back[1] = 40;
+        drop(front);
+        // `back` still holds the exclusive borrow.
+        assert!(cell.try_borrow().is_err());
+        assert!(cell.try_borrow_mut().is_err());
+        drop(back);
+        assert_eq!(*cell.borrow(), [10, 2, 3, 40]);
+        assert!(cell.try_borrow_mut().is_ok());
+    }
+
+    #[cfg(feature = "track-borrows")]
+    #[test]
+    fn reports_outstanding_borrows() {
+        let cell = RefCell::new(1);
+        let line = line!() + 1;
+        let a = cell.borrow();
+        let b = cell.borrow();
+        let err = cell.try_borrow_mut().unwrap_err().to_string();
+        assert!(err.starts_with("already borrowed, borrowed at "));
+        assert!(err.contains(&format!("{}:{}:", file!(), line)));
+        assert!(err.contains(&format!("{}:{}:", file!(), line + 1)));
+        drop(a);
+        let err = cell.try_borrow_mut().unwrap_err().to_string();
+        assert!(!err.contains(&format!("{}:{}:", file!(), line)));
+        drop(b);
+
+        let line = line!() + 1;
+        let _c = cell.borrow_mut();
+        let err = cell.try_borrow().unwrap_err().to_string();
+        assert!(err.starts_with("already mutably borrowed, borrowed at "));
+        assert!(err.contains(&format!("{}:{}:", file!(), line)));
+    }
 }
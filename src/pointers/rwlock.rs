@@ -0,0 +1,214 @@
+use std::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+// The same states as `RefCell`'s, packed into one word: the low bit is the
+// writer, and the bits above the next one count readers. `WRITER_WAITING` is
+// set by a writer that can't get in yet, and keeps new readers out until it has
+// had its turn, so a steady stream of readers can't starve writers.
+const WRITER: usize = 1;
+const WRITER_WAITING: usize = 2;
+const READER: usize = 4;
+
+pub struct RwLock<T> {
+    v: UnsafeCell<T>,
+    state: AtomicUsize,
+}
+
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub fn new(t: T) -> Self {
+        Self {
+            v: UnsafeCell::new(t),
+            state: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn read(&self) -> ReadGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard;
+            }
+            while self.state.load(Ordering::Relaxed) & (WRITER | WRITER_WAITING) != 0 {
+                thread::yield_now();
+            }
+        }
+    }
+
+    pub fn try_read(&self) -> Option<ReadGuard<'_, T>> {
+        let mut state = self.state.load(Ordering::Relaxed);
+        // Only give up because of a writer, not because another reader got in
+        // between our load and the exchange.
+        while state & (WRITER | WRITER_WAITING) == 0 {
+            match self.state.compare_exchange_weak(
+                state,
+                state + READER,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Some(ReadGuard { lock: self }),
+                Err(s) => state = s,
+            }
+        }
+        None
+    }
+
+    pub fn write(&self) -> WriteGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_write() {
+                return guard;
+            }
+            // Let readers drain, and keep new ones from coming in. Another
+            // writer taking the lock clears the bit, so set it again each round.
+            let state = self.state.fetch_or(WRITER_WAITING, Ordering::Relaxed);
+            if state & !WRITER_WAITING != 0 {
+                thread::yield_now();
+            }
+        }
+    }
+
+    pub fn try_write(&self) -> Option<WriteGuard<'_, T>> {
+        let mut state = self.state.load(Ordering::Relaxed);
+        // A waiting writer doesn't hold the lock, so it's fine to take it
+        // anyway. Clearing the bit makes other waiting writers set it again.
+        while state & !WRITER_WAITING == 0 {
+            match self.state.compare_exchange_weak(
+                state,
+                WRITER,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Some(WriteGuard { lock: self }),
+                Err(s) => state = s,
+            }
+        }
+        None
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.v.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.v.into_inner()
+    }
+}
+
+pub struct ReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> Deref for ReadGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        // Safety: we're counted as a reader, so there is no writer.
+        unsafe { &*self.lock.v.get() }
+    }
+}
+
+impl<T> Drop for ReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.fetch_sub(READER, Ordering::Release);
+    }
+}
+
+pub struct WriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> Deref for WriteGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        // Safety: see `deref_mut`.
+        unsafe { &*self.lock.v.get() }
+    }
+}
+
+impl<T> DerefMut for WriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: we hold the writer bit, so there are no readers and no other writer.
+        unsafe { &mut *self.lock.v.get() }
+    }
+}
+
+impl<T> Drop for WriteGuard<'_, T> {
+    fn drop(&mut self) {
+        // Leave `WRITER_WAITING` alone, it belongs to whoever is waiting.
+        self.lock.state.fetch_and(!WRITER, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_and_exclusive() {
+        let lock = RwLock::new(vec![1]);
+        let r1 = lock.read();
+        let r2 = lock.try_read().unwrap();
+        assert!(lock.try_write().is_none());
+        assert_eq!((r1.len(), r2.len()), (1, 1));
+        drop((r1, r2));
+
+        let mut w = lock.try_write().unwrap();
+        w.push(2);
+        assert!(lock.try_read().is_none());
+        assert!(lock.try_write().is_none());
+        drop(w);
+        assert_eq!(*lock.read(), [1, 2]);
+        assert_eq!(lock.into_inner(), [1, 2]);
+    }
+
+    #[test]
+    fn gogo() {
+        let l: &'static _ = Box::leak(Box::new(RwLock::new((0, 0))));
+        let writers = (0..5)
+            .map(|_| {
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        let mut w = l.write();
+                        w.0 += 1;
+                        w.1 += 1;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        let readers = (0..5)
+            .map(|_| {
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        let r = l.read();
+                        // A writer never shows up halfway through.
+                        assert_eq!(r.0, r.1);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in writers.into_iter().chain(readers) {
+            handle.join().unwrap();
+        }
+        assert_eq!(*l.read(), (5 * 100, 5 * 100));
+    }
+
+    #[test]
+    fn writer_preference() {
+        let l: &'static _ = Box::leak(Box::new(RwLock::new(0)));
+        let r = l.read();
+        let writer = thread::spawn(move || {
+            *l.write() += 1;
+        });
+        while l.state.load(Ordering::Relaxed) & WRITER_WAITING == 0 {
+            thread::yield_now();
+        }
+        // There's only a reader in, but the waiting writer goes first.
+        assert!(l.try_read().is_none());
+        drop(r);
+        writer.join().unwrap();
+        assert_eq!(*l.read(), 1);
+    }
+}
@@ -0,0 +1,236 @@
+use super::parking::{wait, wake_all, ParkingMutex};
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU32, AtomicUsize, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+// Hands out up to a fixed number of permits at a time. Waiters aren't served in
+// any particular order: whenever permits come back, everyone waiting tries
+// again, and whoever gets there first wins.
+pub struct Semaphore {
+    permits: AtomicUsize,
+    // Bumped whenever permits come back, blocked threads wait for it to change.
+    released: AtomicU32,
+    // Tasks waiting in `acquire_async`, woken along with the threads.
+    wakers: ParkingMutex<Vec<Waker>>,
+}
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Self {
+        Self {
+            permits: AtomicUsize::new(permits),
+            released: AtomicU32::new(0),
+            wakers: ParkingMutex::new(Vec::new()),
+        }
+    }
+
+    pub fn available_permits(&self) -> usize {
+        self.permits.load(Ordering::Relaxed)
+    }
+
+    pub fn try_acquire(&self) -> Option<Permit<'_>> {
+        self.try_acquire_many(1)
+    }
+
+    pub fn try_acquire_many(&self, n: usize) -> Option<Permit<'_>> {
+        let mut permits = self.permits.load(Ordering::Relaxed);
+        while permits >= n {
+            // Acquire pairs with the Release in `add_permits`, so whatever the
+            // previous holder did with the resource is visible to us.
+            match self.permits.compare_exchange_weak(
+                permits,
+                permits - n,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Some(Permit { sem: self, n }),
+                Err(p) => permits = p,
+            }
+        }
+        None
+    }
+
+    pub fn acquire(&self) -> Permit<'_> {
+        self.acquire_many(1)
+    }
+
+    // Blocks until `n` permits are available at once. Waiting for more than
+    // will ever be handed out, say more than were given to `new` when nobody
+    // calls `add_permits`, blocks forever. Use `try_acquire_many` if `n` may be
+    // that large.
+    pub fn acquire_many(&self, n: usize) -> Permit<'_> {
+        loop {
+            // Read before trying, so permits that come back in between make
+            // the wait below return right away.
+            let released = self.released.load(Ordering::Acquire);
+            if let Some(permit) = self.try_acquire_many(n) {
+                return permit;
+            }
+            wait(&self.released, released);
+        }
+    }
+
+    pub fn acquire_async(&self) -> Acquire<'_> {
+        self.acquire_many_async(1)
+    }
+
+    // Like `acquire_many`, the future stays pending forever if `n` permits
+    // never are available at once.
+    pub fn acquire_many_async(&self, n: usize) -> Acquire<'_> {
+        Acquire { sem: self, n }
+    }
+
+    pub fn add_permits(&self, n: usize) {
+        self.permits.fetch_add(n, Ordering::Release);
+        self.released.fetch_add(1, Ordering::Release);
+        wake_all(&self.released);
+        // Wake outside the lock, a waker may well poll the task right away.
+        let wakers = std::mem::take(&mut *self.wakers.lock());
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+// Gives its permits back when dropped.
+pub struct Permit<'a> {
+    sem: &'a Semaphore,
+    n: usize,
+}
+
+impl Permit<'_> {
+    pub fn permits(&self) -> usize {
+        self.n
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.sem.add_permits(self.n);
+    }
+}
+
+pub struct Acquire<'a> {
+    sem: &'a Semaphore,
+    n: usize,
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = Permit<'a>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(permit) = self.sem.try_acquire_many(self.n) {
+            return Poll::Ready(permit);
+        }
+        let mut wakers = self.sem.wakers.lock();
+        // We may be polled again before any permits come back, don't pile up
+        // wakers then. If we're done below, ours stays in the list, that's
+        // just one extra wake-up the next time permits come back.
+        if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+        drop(wakers);
+        // Permits that came back before we were in the list didn't wake us.
+        match self.sem.try_acquire_many(self.n) {
+            Some(permit) => Poll::Ready(permit),
+            None => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::future::executor::new_executor_and_spawner;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn permits() {
+        let sem = Semaphore::new(3);
+        let one = sem.acquire();
+        let two = sem.acquire_many(2);
+        assert_eq!((one.permits(), two.permits()), (1, 2));
+        assert!(sem.try_acquire().is_none());
+        drop(two);
+        assert!(sem.try_acquire_many(3).is_none());
+        assert_eq!(sem.try_acquire_many(2).unwrap().permits(), 2);
+        drop(one);
+        assert_eq!(sem.available_permits(), 3);
+    }
+
+    #[test]
+    fn more_than_there_are() {
+        let sem = Semaphore::new(2);
+        // `acquire_many(3)` would block forever here.
+        assert!(sem.try_acquire_many(3).is_none());
+        assert_eq!(sem.available_permits(), 2);
+        // Unless there's more to come.
+        sem.add_permits(1);
+        assert_eq!(sem.try_acquire_many(3).unwrap().permits(), 3);
+        assert_eq!(sem.available_permits(), 3);
+    }
+
+    #[test]
+    fn limits_concurrency() {
+        let sem: &'static _ = Box::leak(Box::new(Semaphore::new(3)));
+        let inside: &'static _ = Box::leak(Box::new(AtomicUsize::new(0)));
+        let max: &'static _ = Box::leak(Box::new(AtomicUsize::new(0)));
+        let handles = (0..10)
+            .map(|_| {
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        let _permit = sem.acquire();
+                        let now = inside.fetch_add(1, Ordering::Relaxed) + 1;
+                        max.fetch_max(now, Ordering::Relaxed);
+                        thread::yield_now();
+                        inside.fetch_sub(1, Ordering::Relaxed);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert!(max.load(Ordering::Relaxed) <= 3);
+        assert_eq!(sem.available_permits(), 3);
+    }
+
+    #[test]
+    fn repolls_dont_pile_up_wakers() {
+        let sem = Semaphore::new(0);
+        let mut acquire = sem.acquire_async();
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        for _ in 0..3 {
+            assert!(Pin::new(&mut acquire).poll(&mut cx).is_pending());
+        }
+        assert_eq!(sem.wakers.lock().len(), 1);
+        sem.add_permits(1);
+        assert!(sem.wakers.lock().is_empty());
+        assert!(Pin::new(&mut acquire).poll(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn acquire_async() {
+        let sem: &'static _ = Box::leak(Box::new(Semaphore::new(0)));
+        let done: &'static _ = Box::leak(Box::new(AtomicUsize::new(0)));
+        let (executor, spawner) = new_executor_and_spawner();
+        for n in 1..=3 {
+            spawner.spawn(async move {
+                let _permit = sem.acquire_many_async(n).await;
+                done.fetch_add(1, Ordering::Relaxed);
+            });
+        }
+        drop(spawner);
+        let releaser = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            assert_eq!(done.load(Ordering::Relaxed), 0);
+            sem.add_permits(3);
+        });
+        executor.run();
+        releaser.join().unwrap();
+        assert_eq!(done.load(Ordering::Relaxed), 3);
+        assert_eq!(sem.available_permits(), 3);
+    }
+}
@@ -0,0 +1,488 @@
+use super::vec::{slice_range, RawValIter, RawVec};
+use std::{
+    fmt,
+    iter::FromIterator,
+    mem::{self, MaybeUninit},
+    ops::{Deref, DerefMut, Range, RangeBounds},
+    ptr,
+};
+
+enum Data<T, const N: usize> {
+    Inline(MaybeUninit<[T; N]>),
+    Heap(RawVec<T>),
+}
+
+// A `Vec` that keeps up to `N` elements inline, and only moves them to the
+// heap once it grows past that. It doesn't move back on its own, only
+// `shrink_to_fit` does.
+pub struct SmallVec<T, const N: usize> {
+    data: Data<T, N>,
+    len: usize,
+}
+
+impl<T, const N: usize> SmallVec<T, N> {
+    const IS_ZST: bool = mem::size_of::<T>() == 0;
+
+    fn ptr(&self) -> *const T {
+        match &self.data {
+            Data::Inline(array) => array.as_ptr() as *const T,
+            Data::Heap(buf) => buf.ptr(),
+        }
+    }
+
+    fn ptr_mut(&mut self) -> *mut T {
+        match &mut self.data {
+            Data::Inline(array) => array.as_mut_ptr() as *mut T,
+            Data::Heap(buf) => buf.ptr(),
+        }
+    }
+
+    pub fn new() -> Self {
+        SmallVec {
+            data: Data::Inline(MaybeUninit::uninit()),
+            len: 0,
+        }
+    }
+
+    pub fn with_capacity(cap: usize) -> Self {
+        let mut v = Self::new();
+        v.reserve(cap);
+        v
+    }
+
+    pub fn capacity(&self) -> usize {
+        match &self.data {
+            // Like in `RawVec`, zero-sized types never need memory.
+            Data::Inline(_) if Self::IS_ZST => !0,
+            Data::Inline(_) => N,
+            Data::Heap(buf) => buf.capacity(),
+        }
+    }
+
+    // Whether the elements have moved to the heap.
+    pub fn spilled(&self) -> bool {
+        matches!(self.data, Data::Heap(_))
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        let needed = self.len.checked_add(additional).expect("capacity overflow");
+        if needed <= self.capacity() {
+            return;
+        }
+        match &mut self.data {
+            Data::Inline(_) => self.spill(needed.max(2 * N)),
+            Data::Heap(buf) => buf.reserve(self.len, additional),
+        }
+    }
+
+    #[cold]
+    fn spill(&mut self, cap: usize) {
+        let buf = RawVec::with_capacity(cap);
+        // SAFETY: The new buffer has room for all of them, and the old inline
+        // array never drops them.
+        unsafe { ptr::copy_nonoverlapping(self.ptr(), buf.ptr(), self.len) };
+        self.data = Data::Heap(buf);
+    }
+
+    // Moves the elements back inline if they fit, and otherwise shrinks the
+    // heap buffer.
+    pub fn shrink_to_fit(&mut self) {
+        if let Data::Heap(buf) = &mut self.data {
+            if self.len > N {
+                buf.shrink_to(self.len);
+                return;
+            }
+            let mut array = MaybeUninit::<[T; N]>::uninit();
+            unsafe {
+                ptr::copy_nonoverlapping(buf.ptr(), array.as_mut_ptr() as *mut T, self.len);
+            }
+            // Frees the buffer, the elements now live in `array`.
+            self.data = Data::Inline(array);
+        }
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+        let tail =
+            ptr::slice_from_raw_parts_mut(unsafe { self.ptr_mut().add(len) }, self.len - len);
+        // Shorten first, see `Vec::truncate`.
+        self.len = len;
+        unsafe { ptr::drop_in_place(tail) };
+    }
+
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    pub fn push(&mut self, elem: T) {
+        if self.len == self.capacity() {
+            self.reserve(1);
+        }
+        unsafe {
+            ptr::write(self.ptr_mut().add(self.len), elem);
+        }
+        self.len += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            None
+        } else {
+            self.len -= 1;
+            unsafe { Some(ptr::read(self.ptr().add(self.len))) }
+        }
+    }
+
+    pub fn insert(&mut self, index: usize, elem: T) {
+        assert!(index <= self.len, "index out of bounds");
+        if self.len == self.capacity() {
+            self.reserve(1);
+        }
+        unsafe {
+            let ptr = self.ptr_mut();
+            ptr::copy(ptr.add(index), ptr.add(index + 1), self.len - index);
+            ptr::write(ptr.add(index), elem);
+        }
+        self.len += 1;
+    }
+
+    pub fn remove(&mut self, index: usize) -> T {
+        assert!(index < self.len, "index out of bounds");
+        unsafe {
+            self.len -= 1;
+            let ptr = self.ptr_mut();
+            let result = ptr::read(ptr.add(index));
+            ptr::copy(ptr.add(index + 1), ptr.add(index), self.len - index);
+            result
+        }
+    }
+
+    pub fn drain(&mut self, range: impl RangeBounds<usize>) -> Drain<'_, T, N> {
+        let Range { start, end } = slice_range(range, self.len);
+        let tail_len = self.len - end;
+        unsafe {
+            let iter = RawValIter::new(std::slice::from_raw_parts(
+                self.ptr().add(start),
+                end - start,
+            ));
+            // If the `Drain` is forgotten, the drained range and the tail leak.
+            self.len = start;
+            Drain {
+                vec: self,
+                iter,
+                tail_start: end,
+                tail_len,
+            }
+        }
+    }
+}
+
+impl<T, const N: usize> Drop for SmallVec<T, N> {
+    fn drop(&mut self) {
+        // The heap buffer, if any, frees itself.
+        self.clear();
+    }
+}
+
+impl<T, const N: usize> Default for SmallVec<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Deref for SmallVec<T, N> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        unsafe { std::slice::from_raw_parts(self.ptr(), self.len) }
+    }
+}
+
+impl<T, const N: usize> DerefMut for SmallVec<T, N> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr_mut(), self.len) }
+    }
+}
+
+impl<T: Clone, const N: usize> Clone for SmallVec<T, N> {
+    fn clone(&self) -> Self {
+        self.iter().cloned().collect()
+    }
+}
+
+impl<T: fmt::Debug, const N: usize> fmt::Debug for SmallVec<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: PartialEq<U>, U, const N: usize, const M: usize> PartialEq<SmallVec<U, M>>
+    for SmallVec<T, N>
+{
+    fn eq(&self, other: &SmallVec<U, M>) -> bool {
+        self[..] == other[..]
+    }
+}
+
+impl<T: PartialEq<U>, U, const N: usize> PartialEq<[U]> for SmallVec<T, N> {
+    fn eq(&self, other: &[U]) -> bool {
+        self[..] == other[..]
+    }
+}
+
+impl<T: PartialEq<U>, U, const N: usize, const M: usize> PartialEq<[U; M]> for SmallVec<T, N> {
+    fn eq(&self, other: &[U; M]) -> bool {
+        self[..] == other[..]
+    }
+}
+
+impl<T: Eq, const N: usize> Eq for SmallVec<T, N> {}
+
+impl<T, const N: usize> Extend<T> for SmallVec<T, N> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for elem in iter {
+            self.push(elem);
+        }
+    }
+}
+
+impl<T, const N: usize> FromIterator<T> for SmallVec<T, N> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut v = Self::new();
+        v.extend(iter);
+        v
+    }
+}
+
+// Keeps track of the elements by index rather than by pointer, since inline
+// ones move along with the iterator.
+pub struct IntoIter<T, const N: usize> {
+    // With its `len` at 0, so it only frees the buffer.
+    vec: SmallVec<T, N>,
+    start: usize,
+    end: usize,
+}
+
+impl<T, const N: usize> Iterator for IntoIter<T, N> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.start == self.end {
+            return None;
+        }
+        self.start += 1;
+        unsafe { Some(ptr::read(self.vec.ptr().add(self.start - 1))) }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.end - self.start;
+        (len, Some(len))
+    }
+}
+
+impl<T, const N: usize> DoubleEndedIterator for IntoIter<T, N> {
+    fn next_back(&mut self) -> Option<T> {
+        if self.start == self.end {
+            return None;
+        }
+        self.end -= 1;
+        unsafe { Some(ptr::read(self.vec.ptr().add(self.end))) }
+    }
+}
+
+impl<T, const N: usize> Drop for IntoIter<T, N> {
+    fn drop(&mut self) {
+        for _ in &mut *self {}
+    }
+}
+
+impl<T, const N: usize> IntoIterator for SmallVec<T, N> {
+    type Item = T;
+    type IntoIter = IntoIter<T, N>;
+
+    fn into_iter(mut self) -> Self::IntoIter {
+        let end = mem::replace(&mut self.len, 0);
+        IntoIter {
+            vec: self,
+            start: 0,
+            end,
+        }
+    }
+}
+
+pub struct Drain<'a, T: 'a, const N: usize> {
+    vec: &'a mut SmallVec<T, N>,
+    iter: RawValIter<T>,
+    tail_start: usize,
+    tail_len: usize,
+}
+
+impl<T, const N: usize> Iterator for Drain<'_, T, N> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.iter.next()
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<T, const N: usize> DoubleEndedIterator for Drain<'_, T, N> {
+    fn next_back(&mut self) -> Option<T> {
+        self.iter.next_back()
+    }
+}
+
+impl<T, const N: usize> Drop for Drain<'_, T, N> {
+    fn drop(&mut self) {
+        // Like `vec::Drain`, the tail is moved back even if a `drop` panics.
+        struct MoveTail<'r, 'a, T, const N: usize>(&'r mut Drain<'a, T, N>);
+
+        impl<T, const N: usize> Drop for MoveTail<'_, '_, T, N> {
+            fn drop(&mut self) {
+                let drain = &mut *self.0;
+                let start = drain.vec.len;
+                unsafe {
+                    let ptr = drain.vec.ptr_mut();
+                    ptr::copy(ptr.add(drain.tail_start), ptr.add(start), drain.tail_len);
+                }
+                drain.vec.len = start + drain.tail_len;
+            }
+        }
+
+        let guard = MoveTail(self);
+        for _ in &mut guard.0.iter {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pointers::{cell::Cell, test_util::DropCounter};
+
+    fn is_inline<T, const N: usize>(v: &SmallVec<T, N>) -> bool {
+        let start = v as *const _ as usize;
+        let ptr = v.as_ptr() as usize;
+        start <= ptr && ptr < start + mem::size_of_val(v)
+    }
+
+    #[test]
+    fn spills_past_n() {
+        let mut v = SmallVec::<String, 4>::new();
+        for i in 0..4 {
+            v.push(i.to_string());
+        }
+        assert!(!v.spilled() && is_inline(&v));
+        assert_eq!(v.capacity(), 4);
+
+        v.insert(0, "x".to_string());
+        assert!(v.spilled() && !is_inline(&v));
+        assert_eq!(v.capacity(), 8);
+        assert_eq!(v, ["x", "0", "1", "2", "3"]);
+
+        assert_eq!(v.remove(0), "x");
+        v.shrink_to_fit();
+        assert!(!v.spilled() && is_inline(&v));
+        assert_eq!(v.pop().as_deref(), Some("3"));
+        assert_eq!(v, ["0", "1", "2"]);
+    }
+
+    #[test]
+    fn with_capacity() {
+        assert!(!SmallVec::<u8, 8>::with_capacity(8).spilled());
+        let v = SmallVec::<u8, 8>::with_capacity(9);
+        assert!(v.spilled());
+        assert_eq!(v.capacity(), 16);
+
+        let mut v = SmallVec::<u8, 0>::new();
+        assert_eq!(v.capacity(), 0);
+        v.push(1);
+        assert!(v.spilled());
+        assert_eq!(v, [1]);
+    }
+
+    #[test]
+    fn drain() {
+        let mut v = (0..6).map(|i| i.to_string()).collect::<SmallVec<_, 8>>();
+        let drained = v.drain(1..4).collect::<Vec<_>>();
+        assert_eq!(drained, ["1", "2", "3"]);
+        assert_eq!(v, ["0", "4", "5"]);
+
+        let mut v = (0..20).collect::<SmallVec<_, 8>>();
+        let mut drain = v.drain(5..);
+        assert_eq!(drain.next_back(), Some(19));
+        assert_eq!(drain.next(), Some(5));
+        drop(drain);
+        assert_eq!(v, [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn into_iter() {
+        let v = (0..3).map(|i| i.to_string()).collect::<SmallVec<_, 4>>();
+        let mut iter = v.into_iter();
+        assert_eq!(iter.next().as_deref(), Some("0"));
+        // Inline elements move along with the iterator.
+        let mut iter = Box::new(iter);
+        assert_eq!(iter.next_back().as_deref(), Some("2"));
+        assert_eq!(iter.size_hint(), (1, Some(1)));
+        assert_eq!(iter.collect::<Vec<_>>(), ["1"]);
+
+        let v = (0..10).collect::<SmallVec<_, 4>>();
+        assert_eq!(
+            v.into_iter().rev().collect::<Vec<_>>(),
+            (0..10).rev().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn drops_elements() {
+        let drops = Cell::new(0);
+        let mut v = SmallVec::<_, 2>::new();
+        for _ in 0..2 {
+            v.push(DropCounter { drops: &drops });
+        }
+        drop(v);
+        assert_eq!(drops.get(), 2);
+
+        drops.set(0);
+        let mut v = SmallVec::<_, 2>::new();
+        for _ in 0..5 {
+            v.push(DropCounter { drops: &drops });
+        }
+        v.truncate(4);
+        assert_eq!(drops.get(), 1);
+        drop(v.drain(1..2));
+        assert_eq!(drops.get(), 2);
+        let mut iter = v.into_iter();
+        iter.next();
+        assert_eq!(drops.get(), 3);
+        drop(iter);
+        assert_eq!(drops.get(), 5);
+    }
+
+    #[test]
+    fn zero_sized() {
+        let mut v = SmallVec::<(), 2>::new();
+        for _ in 0..100 {
+            v.push(());
+        }
+        assert!(!v.spilled());
+        assert_eq!((v.len(), v.capacity()), (100, usize::MAX));
+        v.insert(50, ());
+        v.remove(0);
+        assert_eq!(v.drain(10..20).count(), 10);
+        assert_eq!(v.into_iter().count(), 90);
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity overflow")]
+    fn zero_sized_overflow() {
+        let mut v = SmallVec::<(), 2>::new();
+        v.push(());
+        v.reserve(usize::MAX);
+    }
+}
@@ -0,0 +1,74 @@
+use super::cell;
+use std::{
+    cell::Cell,
+    ops::DerefMut,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Barrier,
+    },
+    thread,
+};
+
+// Counts how many of these were dropped.
+pub(crate) struct DropCounter<'a> {
+    pub(crate) drops: &'a cell::Cell<usize>,
+}
+
+impl Drop for DropCounter<'_> {
+    fn drop(&mut self) {
+        self.drops.set(self.drops.get() + 1);
+    }
+}
+
+const THREADS: usize = 10;
+const ROUNDS: usize = 100;
+
+// Has a bunch of threads take a fair lock over and over, and checks that none
+// of them waits while the lock is handed out more than once to each of the
+// others. `lock` is called with a closure that it must call right after it got
+// in line, and returns the guard once it's our turn. The lock must start out
+// holding 0, it counts the grants too, to check nobody lost an update.
+//
+// What the threads see is only read after they got in line, so a slow thread
+// may see fewer grants than there were, never more. That makes this pass for
+// any schedule of a fair lock.
+pub(crate) fn assert_bounded_wait<L, G>(lock: L)
+where
+    L: Fn(&dyn Fn()) -> G + Copy + Send + 'static,
+    G: DerefMut<Target = usize>,
+{
+    // Only changed while the lock is held.
+    let grants: &'static _ = Box::leak(Box::new(AtomicUsize::new(0)));
+    // Start everyone at once, so they actually compete for the lock.
+    let start: &'static _ = Box::leak(Box::new(Barrier::new(THREADS)));
+    let handles = (0..THREADS)
+        .map(|_| {
+            thread::spawn(move || {
+                start.wait();
+                let mut max_skew = 0;
+                for _ in 0..ROUNDS {
+                    let enqueued = Cell::new(0);
+                    let mut guard = lock(&|| enqueued.set(grants.load(Ordering::SeqCst)));
+                    let granted = grants.fetch_add(1, Ordering::SeqCst);
+                    assert_eq!(*guard, granted);
+                    *guard += 1;
+                    max_skew = usize::max(max_skew, granted - enqueued.get());
+                    // Give the others a chance to line up behind us.
+                    thread::yield_now();
+                    drop(guard);
+                }
+                max_skew
+            })
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        let max_skew = handle.join().unwrap();
+        assert!(
+            max_skew < THREADS,
+            "waited for {} grants with only {} other threads",
+            max_skew,
+            THREADS - 1
+        );
+    }
+    assert_eq!(grants.load(Ordering::SeqCst), THREADS * ROUNDS);
+}
@@ -0,0 +1,165 @@
+use std::{
+    cell::UnsafeCell,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+// A lock that's handed out in the order it was asked for, like the tickets at
+// a deli counter. `unlock::Mutex` goes to whoever wins the compare-exchange, so
+// an unlucky thread can lose forever; here it's at most behind everyone who was
+// already waiting.
+pub struct TicketLock<T> {
+    v: UnsafeCell<T>,
+    next_ticket: AtomicUsize,
+    now_serving: AtomicUsize,
+}
+
+unsafe impl<T: Send> Sync for TicketLock<T> {}
+
+impl<T> TicketLock<T> {
+    pub fn new(t: T) -> Self {
+        Self {
+            v: UnsafeCell::new(t),
+            next_ticket: AtomicUsize::new(0),
+            now_serving: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn lock(&self) -> TicketLockGuard<'_, T> {
+        let ticket = self.take_ticket();
+        self.wait_for(ticket)
+    }
+
+    fn take_ticket(&self) -> usize {
+        // Relaxed, the ticket only decides the order, `now_serving` is what
+        // hands over the value. Tickets wrap around, but that takes
+        // `usize::MAX` waiting threads to go wrong.
+        self.next_ticket.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn wait_for(&self, ticket: usize) -> TicketLockGuard<'_, T> {
+        while self.now_serving.load(Ordering::Acquire) != ticket {
+            thread::yield_now();
+        }
+        TicketLockGuard::new(self, ticket)
+    }
+
+    pub fn try_lock(&self) -> Option<TicketLockGuard<'_, T>> {
+        // Only take a ticket if it's served right away.
+        let ticket = self.now_serving.load(Ordering::Acquire);
+        self.next_ticket
+            .compare_exchange(
+                ticket,
+                ticket.wrapping_add(1),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .ok()
+            .map(|_| TicketLockGuard::new(self, ticket))
+    }
+
+    pub fn with_lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut self.lock())
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.v.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.v.into_inner()
+    }
+}
+
+/// ```compile_fail
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<crust_of_rust::pointers::ticket_lock::TicketLockGuard<'static, std::cell::Cell<i32>>>();
+/// ```
+pub struct TicketLockGuard<'a, T> {
+    lock: &'a TicketLock<T>,
+    ticket: usize,
+    // Like `unlock::MutexGuard`, so sharing the guard needs `T: Sync`.
+    _not_sync: PhantomData<*const ()>,
+}
+
+// SAFETY: Sharing the guard only shares a `&T`.
+unsafe impl<T: Sync> Sync for TicketLockGuard<'_, T> {}
+
+impl<'a, T> TicketLockGuard<'a, T> {
+    // Must only be called once `ticket` is being served.
+    fn new(lock: &'a TicketLock<T>, ticket: usize) -> Self {
+        TicketLockGuard {
+            lock,
+            ticket,
+            _not_sync: PhantomData,
+        }
+    }
+}
+
+impl<T> Deref for TicketLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        // Safety: see `deref_mut`.
+        unsafe { &*self.lock.v.get() }
+    }
+}
+
+impl<T> DerefMut for TicketLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: our ticket is being served, therefore we can create a mutable reference.
+        unsafe { &mut *self.lock.v.get() }
+    }
+}
+
+impl<T> Drop for TicketLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock
+            .now_serving
+            .store(self.ticket.wrapping_add(1), Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gogo() {
+        let l: &'static _ = Box::leak(Box::new(TicketLock::new(0)));
+        crate::pointers::test_util::assert_bounded_wait(move |enqueued: &dyn Fn()| {
+            let ticket = l.take_ticket();
+            enqueued();
+            l.wait_for(ticket)
+        });
+    }
+
+    #[test]
+    fn guard_is_sync_for_sync_values() {
+        fn assert_sync<T: Sync>() {}
+        assert_sync::<TicketLockGuard<'static, i32>>();
+    }
+
+    #[test]
+    fn first_come_first_served() {
+        let l: &'static _ = Box::leak(Box::new(TicketLock::new(Vec::new())));
+        let guard = l.lock();
+        assert!(l.try_lock().is_none());
+        let handles = (0..5)
+            .map(|id| {
+                let handle = thread::spawn(move || l.with_lock(|v| v.push(id)));
+                // Wait until it has its ticket before the next one asks.
+                while l.next_ticket.load(Ordering::Relaxed) != id + 2 {
+                    thread::yield_now();
+                }
+                handle
+            })
+            .collect::<Vec<_>>();
+        drop(guard);
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(*l.try_lock().unwrap(), [0, 1, 2, 3, 4]);
+    }
+}
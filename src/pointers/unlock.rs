@@ -1,28 +1,54 @@
+use crate::sync::{
+    atomic::{AtomicBool, Ordering},
+    thread, UnsafeCell,
+};
+#[cfg(feature = "deadlock-detection")]
+use std::panic::Location;
 use std::{
-    cell::UnsafeCell,
-    sync::atomic::{AtomicBool, Ordering},
-    thread,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    panic::{RefUnwindSafe, UnwindSafe},
+    sync::{LockResult, PoisonError, TryLockError, TryLockResult},
 };
 
+#[cfg(feature = "deadlock-detection")]
+mod lock_order;
+
 const UNLOCKED: bool = false;
 const LOCKED: bool = true;
 
 pub struct Mutex<T> {
     v: UnsafeCell<T>,
     locked: AtomicBool,
+    // Set when a guard is dropped while its thread panics, since the value may
+    // have been left halfway through a change.
+    poisoned: AtomicBool,
+    #[cfg(feature = "deadlock-detection")]
+    id: usize,
 }
 
 unsafe impl<T: Send> Sync for Mutex<T> {}
 
+// A panic while the lock is held poisons it, so whoever looks at the value
+// afterwards is told about it.
+impl<T> UnwindSafe for Mutex<T> {}
+impl<T> RefUnwindSafe for Mutex<T> {}
+
 impl<T> Mutex<T> {
     pub fn new(t: T) -> Self {
         Self {
             locked: AtomicBool::new(UNLOCKED),
+            poisoned: AtomicBool::new(false),
             v: UnsafeCell::new(t),
+            #[cfg(feature = "deadlock-detection")]
+            id: lock_order::next_id(),
         }
     }
 
-    pub fn with_lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+    #[cfg_attr(feature = "deadlock-detection", track_caller)]
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+        #[cfg(feature = "deadlock-detection")]
+        lock_order::check(self.id, Location::caller());
         while self
             .locked
             .compare_exchange_weak(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
@@ -33,10 +59,118 @@ impl<T> Mutex<T> {
             }
             thread::yield_now();
         }
+        self.guard()
+    }
+
+    #[cfg_attr(feature = "deadlock-detection", track_caller)]
+    pub fn try_lock(&self) -> TryLockResult<MutexGuard<'_, T>> {
+        if self
+            .locked
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return Err(TryLockError::WouldBlock);
+        }
+        Ok(self.guard()?)
+    }
+
+    // Must only be called with the lock held.
+    #[cfg_attr(feature = "deadlock-detection", track_caller)]
+    fn guard(&self) -> LockResult<MutexGuard<'_, T>> {
+        #[cfg(feature = "deadlock-detection")]
+        lock_order::acquired(self.id, Location::caller());
+        let guard = MutexGuard {
+            lock: self,
+            panicking: thread::panicking(),
+            _not_sync: PhantomData,
+        };
+        // Relaxed is enough, the lock's Acquire orders this after the Release
+        // that unlocked it, which comes after the poisoning store.
+        if self.poisoned.load(Ordering::Relaxed) {
+            Err(PoisonError::new(guard))
+        } else {
+            Ok(guard)
+        }
+    }
+
+    // Ignores poisoning, as it did before the mutex tracked it, since there's
+    // no way to tell the caller. Use `lock` to find out. If `f` panics, the
+    // mutex is unlocked and poisoned.
+    #[cfg_attr(feature = "deadlock-detection", track_caller)]
+    pub fn with_lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let mut guard = self.lock().unwrap_or_else(PoisonError::into_inner);
+        f(&mut guard)
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Ordering::Relaxed)
+    }
+
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        let poisoned = self.poisoned.load(Ordering::Relaxed);
+        // Having `&mut self` means nobody holds the lock.
+        let v = self.v.with_mut(|v| unsafe { &mut *v });
+        if poisoned {
+            Err(PoisonError::new(v))
+        } else {
+            Ok(v)
+        }
+    }
+
+    pub fn into_inner(self) -> LockResult<T> {
+        let poisoned = self.poisoned.into_inner();
+        let v = self.v.into_inner();
+        if poisoned {
+            Err(PoisonError::new(v))
+        } else {
+            Ok(v)
+        }
+    }
+}
+
+/// ```compile_fail
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<crust_of_rust::pointers::unlock::MutexGuard<'static, std::cell::Cell<i32>>>();
+/// ```
+pub struct MutexGuard<'a, T> {
+    pub(super) lock: &'a Mutex<T>,
+    // Whether we were already panicking when the lock was taken. A guard taken
+    // in a `Drop` during unwinding shouldn't poison the mutex on its way out.
+    panicking: bool,
+    // Otherwise the guard would be `Sync` whenever the mutex is, which only
+    // needs `T: Send`, and two threads could share a `&T` through it. It also
+    // makes it `!Send`, like std's, since it's unlocked where it was locked.
+    _not_sync: PhantomData<*const ()>,
+}
+
+// SAFETY: Sharing the guard only shares a `&T`.
+unsafe impl<T: Sync> Sync for MutexGuard<'_, T> {}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        // Safety: see `deref_mut`.
+        self.lock.v.with(|v| unsafe { &*v })
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
         // Safety: we hold the lock, therefore we can create a mutable reference.
-        let r = f(unsafe { &mut *self.v.get() });
-        self.locked.store(UNLOCKED, Ordering::Release);
-        r
+        // Loom only sees the access when the reference is made, not while it's
+        // used, but either way it happens under the lock.
+        self.lock.v.with_mut(|v| unsafe { &mut *v })
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        if !self.panicking && thread::panicking() {
+            self.lock.poisoned.store(true, Ordering::Relaxed);
+        }
+        #[cfg(feature = "deadlock-detection")]
+        lock_order::released(self.lock.id);
+        self.lock.locked.store(UNLOCKED, Ordering::Release);
     }
 }
 
@@ -65,6 +199,109 @@ mod tests {
         assert_eq!(l.with_lock(|v| *v), 10 * 100);
     }
 
+    #[test]
+    fn guards() {
+        let mut m = Mutex::new(vec![1]);
+        let mut guard = m.lock().unwrap();
+        guard.push(2);
+        assert!(matches!(m.try_lock(), Err(TryLockError::WouldBlock)));
+        drop(guard);
+        m.try_lock().unwrap().push(3);
+        m.get_mut().unwrap().push(4);
+        assert_eq!(m.into_inner().unwrap(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn guard_is_sync_for_sync_values() {
+        fn assert_sync<T: Sync>() {}
+        assert_sync::<MutexGuard<'static, i32>>();
+    }
+
+    #[test]
+    fn poisoned_by_panic() {
+        let m: &'static _ = Box::leak(Box::new(Mutex::new(0)));
+        let result = thread::spawn(move || {
+            m.with_lock(|v| {
+                *v += 1;
+                panic!("halfway through");
+            })
+        })
+        .join();
+        assert!(result.is_err());
+        assert!(m.is_poisoned());
+
+        // Still unlocked, and the value can be recovered.
+        let guard = m.lock().err().unwrap().into_inner();
+        assert_eq!(*guard, 1);
+        drop(guard);
+        assert!(matches!(m.try_lock(), Err(TryLockError::Poisoned(_))));
+        // `with_lock` doesn't care.
+        assert_eq!(m.with_lock(|v| *v), 1);
+        assert!(m.is_poisoned());
+    }
+
+    #[test]
+    fn no_poison_when_locked_while_panicking() {
+        struct LockOnDrop<'a>(&'a Mutex<i32>);
+        impl Drop for LockOnDrop<'_> {
+            fn drop(&mut self) {
+                *self.0.lock().unwrap() += 1;
+            }
+        }
+
+        let m = Mutex::new(0);
+        let result = std::panic::catch_unwind(|| {
+            let _l = LockOnDrop(&m);
+            panic!("unwinding");
+        });
+        assert!(result.is_err());
+        assert!(!m.is_poisoned());
+        assert_eq!(m.into_inner().unwrap(), 1);
+    }
+
+    #[cfg(feature = "deadlock-detection")]
+    #[test]
+    fn lock_order_inversion() {
+        let a = Mutex::new(());
+        let b = Mutex::new(());
+        let c = Mutex::new(());
+        // a -> b and b -> c, one at a time, and nested the same way again.
+        let (a_line, b_line) = (line!() + 1, line!() + 2);
+        let ga = a.lock().unwrap();
+        let gb = b.lock().unwrap();
+        drop((ga, gb));
+        b.with_lock(|_| c.with_lock(|_| ()));
+        a.with_lock(|_| b.with_lock(|_| ()));
+
+        // c -> a closes the cycle a -> b -> c -> a.
+        let c_guard = c.lock().unwrap();
+        let a_site = format!("{}:{}:", file!(), line!() + 1);
+        let result = std::panic::catch_unwind(|| a.lock().map(drop));
+        let message = *result.unwrap_err().downcast::<String>().unwrap();
+        assert!(message.contains(&a_site), "{}", message);
+        assert!(
+            message.contains(&format!("{}:{}:", file!(), a_line)),
+            "{}",
+            message
+        );
+        assert!(
+            message.contains(&format!("{}:{}:", file!(), b_line)),
+            "{}",
+            message
+        );
+        // Nothing was locked by the failed attempt.
+        drop(c_guard);
+        assert!(a.try_lock().is_ok());
+    }
+
+    #[cfg(feature = "deadlock-detection")]
+    #[test]
+    #[should_panic(expected = "already held by this thread")]
+    fn relock_on_same_thread() {
+        let a = Mutex::new(0);
+        a.with_lock(|_| a.with_lock(|_| ()));
+    }
+
     #[test]
     fn too_release() {
         let x: &'static _ = Box::leak(Box::new(AtomicUsize::new(0)));
@@ -131,3 +368,26 @@ mod tests {
         //   t1 and t2 thread may saw the different view of x and y.
     }
 }
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::*;
+    use crate::sync::Arc;
+
+    #[test]
+    fn loom_mutual_exclusion() {
+        loom::model(|| {
+            let m = Arc::new(Mutex::new(0));
+            let handles = (0..2)
+                .map(|_| {
+                    let m = Arc::clone(&m);
+                    thread::spawn(move || m.with_lock(|v| *v += 1))
+                })
+                .collect::<Vec<_>>();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+            assert_eq!(*m.lock().unwrap(), 2);
+        });
+    }
+}
@@ -0,0 +1,133 @@
+// With the `deadlock-detection` feature, every `Mutex` gets an ID, and we
+// remember which locks were taken while holding which others. Two threads
+// that take the same two locks in opposite orders can deadlock, even if they
+// happened not to this time, so we panic when a lock is about to be taken in
+// an order that contradicts one seen before, anywhere in the program.
+//
+// Nodes aren't removed when a `Mutex` goes away, IDs are never reused, so
+// stale nodes never take part in a cycle. It just costs some memory.
+
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    panic::Location,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, PoisonError,
+    },
+};
+
+type Site = &'static Location<'static>;
+
+// An edge `a -> b` in the graph: `b` was locked at `acquired` while `a`,
+// locked at `held`, was held by the same thread.
+#[derive(Clone, Copy)]
+struct Edge {
+    held: Site,
+    acquired: Site,
+}
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+static GRAPH: Mutex<BTreeMap<usize, BTreeMap<usize, Edge>>> = Mutex::new(BTreeMap::new());
+
+thread_local! {
+    // The locks this thread holds, and where they were taken.
+    static HELD: RefCell<Vec<(usize, Site)>> = const { RefCell::new(Vec::new()) };
+}
+
+pub(super) fn next_id() -> usize {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+// Called before waiting for lock `id`, panics if that could deadlock.
+pub(super) fn check(id: usize, site: Site) {
+    HELD.with(|held| {
+        let held = held.borrow();
+        if let Some(&(_, at)) = held.iter().find(|&&(h, _)| h == id) {
+            panic!(
+                "lock #{} locked at {} is already held by this thread, locked at {}",
+                id, site, at
+            );
+        }
+        // A panic below would poison the graph, but it's still consistent.
+        let mut graph = GRAPH.lock().unwrap_or_else(PoisonError::into_inner);
+        for &(h, h_site) in held.iter() {
+            if let Some(path) = find_path(&graph, id, h) {
+                drop(graph);
+                let first = path[0].1;
+                panic!(
+                    "lock order inversion: lock #{} locked at {} while holding lock #{} \
+                     (locked at {}), but lock #{} was held at {} when lock #{} was locked at {} \
+                     (cycle: #{}{} -> #{})",
+                    id,
+                    site,
+                    h,
+                    h_site,
+                    id,
+                    first.held,
+                    path[0].0,
+                    first.acquired,
+                    id,
+                    path.iter()
+                        .map(|(to, _)| format!(" -> #{}", to))
+                        .collect::<String>(),
+                    id,
+                );
+            }
+            graph.entry(h).or_default().entry(id).or_insert(Edge {
+                held: h_site,
+                acquired: site,
+            });
+        }
+    });
+}
+
+// Called once lock `id` is held, by `lock` as well as `try_lock`.
+pub(super) fn acquired(id: usize, site: Site) {
+    HELD.with(|held| held.borrow_mut().push((id, site)));
+}
+
+pub(super) fn released(id: usize) {
+    HELD.with(|held| {
+        let mut held = held.borrow_mut();
+        // Guards can be dropped in any order, but never on another thread, so
+        // ours is in the list.
+        let i = held.iter().rposition(|&(h, _)| h == id);
+        debug_assert!(i.is_some(), "released lock #{} that wasn't held", id);
+        if let Some(i) = i {
+            held.remove(i);
+        }
+    });
+}
+
+// The edges of a path from `from` to `to`, as (target, edge) pairs.
+fn find_path(
+    graph: &BTreeMap<usize, BTreeMap<usize, Edge>>,
+    from: usize,
+    to: usize,
+) -> Option<Vec<(usize, Edge)>> {
+    // Depth first, remembering how we got to each node.
+    let mut came_from = BTreeMap::new();
+    let mut stack = vec![from];
+    while let Some(node) = stack.pop() {
+        for (&next, &edge) in graph.get(&node).into_iter().flatten() {
+            if next == from || came_from.contains_key(&next) {
+                continue;
+            }
+            came_from.insert(next, (node, edge));
+            if next == to {
+                let mut path = Vec::new();
+                let mut at = to;
+                while at != from {
+                    let (prev, edge) = came_from[&at];
+                    path.push((at, edge));
+                    at = prev;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            stack.push(next);
+        }
+    }
+    None
+}
@@ -1,57 +1,137 @@
+use super::alloc::{Allocator, Global};
 use std::{
-    alloc::{self, Layout},
+    alloc::{handle_alloc_error, Layout},
+    cmp::Ordering,
+    fmt,
+    hash::{Hash, Hasher},
+    iter::{FromIterator, FusedIterator},
     marker::PhantomData,
-    mem,
-    ops::{Deref, DerefMut},
+    mem::{self, ManuallyDrop},
+    ops::{Bound, Deref, DerefMut, Index, IndexMut, Range, RangeBounds},
     ptr::{self, NonNull},
+    slice::{self, SliceIndex},
 };
 
-pub struct RawVec<T> {
+pub struct RawVec<T, A: Allocator = Global> {
     ptr: NonNull<T>,
     cap: usize,
+    alloc: A,
     _marker: PhantomData<T>,
 }
 
-unsafe impl<T: Send> Send for RawVec<T> {}
-unsafe impl<T: Sync> Sync for RawVec<T> {}
+unsafe impl<T: Send, A: Allocator + Send> Send for RawVec<T, A> {}
+unsafe impl<T: Sync, A: Allocator + Sync> Sync for RawVec<T, A> {}
 
 impl<T> RawVec<T> {
     pub fn new() -> Self {
-        let cap = if mem::size_of::<T>() == 0 { !0 } else { 0 };
+        Self::new_in(Global)
+    }
+
+    pub fn with_capacity(cap: usize) -> Self {
+        Self::with_capacity_in(cap, Global)
+    }
+}
+
+impl<T, A: Allocator> RawVec<T, A> {
+    const IS_ZST: bool = mem::size_of::<T>() == 0;
+
+    pub fn new_in(alloc: A) -> Self {
+        // Zero-sized types never need memory, so they start with all the
+        // capacity there is.
+        let cap = if Self::IS_ZST { !0 } else { 0 };
         Self {
             ptr: NonNull::dangling(),
             cap,
+            alloc,
             _marker: PhantomData,
         }
     }
 
+    pub fn with_capacity_in(cap: usize, alloc: A) -> Self {
+        let mut buf = Self::new_in(alloc);
+        if !Self::IS_ZST {
+            buf.set_capacity(cap);
+        }
+        buf
+    }
+
+    pub fn ptr(&self) -> *mut T {
+        self.ptr.as_ptr()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    pub fn allocator(&self) -> &A {
+        &self.alloc
+    }
+
     fn grow(&mut self) {
-        assert!(mem::size_of::<T>() != 0, "capacity overflow");
-        let (new_cap, new_layout) = if self.cap == 0 {
-            (1, Layout::array::<T>(1).unwrap())
-        } else {
-            let new_cap = 2 * self.cap;
-            let new_layout = Layout::array::<T>(new_cap).unwrap();
-            (new_cap, new_layout)
-        };
-        assert!(
-            new_layout.size() <= isize::MAX as usize,
-            "Allocation too large"
-        );
+        self.reserve(self.cap, 1);
+    }
+
+    // Makes room for `additional` more elements after the first `len`, at
+    // least doubling the capacity if it has to grow at all, so a series of
+    // pushes only reallocates O(log n) times.
+    pub fn reserve(&mut self, len: usize, additional: usize) {
+        let needed = len.checked_add(additional).expect("capacity overflow");
+        if needed > self.cap {
+            self.set_capacity(needed.max(2 * self.cap));
+        }
+    }
+
+    // Like `reserve`, but grows to exactly what's needed.
+    pub fn reserve_exact(&mut self, len: usize, additional: usize) {
+        let needed = len.checked_add(additional).expect("capacity overflow");
+        if needed > self.cap {
+            self.set_capacity(needed);
+        }
+    }
+
+    // Shrinks the buffer to `cap`, which must not be below the number of
+    // elements in use.
+    pub fn shrink_to(&mut self, cap: usize) {
+        if !Self::IS_ZST && cap < self.cap {
+            self.set_capacity(cap);
+        }
+    }
+
+    // Moves the allocation to one of exactly `new_cap` elements.
+    fn set_capacity(&mut self, new_cap: usize) {
+        // The capacity of a ZST buffer is already as large as it gets.
+        assert!(!Self::IS_ZST, "capacity overflow");
+        if new_cap == self.cap {
+            return;
+        }
+        // `Layout::array` also checks that the size fits in an `isize`.
+        let new_layout = Layout::array::<T>(new_cap).expect("capacity overflow");
+        let old_ptr = self.ptr.cast::<u8>();
         let new_ptr = if self.cap == 0 {
-            unsafe { alloc::alloc(new_layout) }
+            self.alloc.allocate(new_layout)
+        } else if new_cap == 0 {
+            unsafe { self.alloc.deallocate(old_ptr, self.layout()) };
+            self.ptr = NonNull::dangling();
+            self.cap = 0;
+            return;
+        } else if new_cap > self.cap {
+            unsafe { self.alloc.grow(old_ptr, self.layout(), new_layout) }
         } else {
-            let old_layout = Layout::array::<T>(self.cap).unwrap();
-            let old_ptr = self.ptr.as_ptr() as *mut u8;
-            unsafe { alloc::realloc(old_ptr, old_layout, new_layout.size()) }
+            unsafe { self.alloc.shrink(old_ptr, self.layout(), new_layout) }
         };
 
-        self.ptr = match NonNull::new(new_ptr as *mut T) {
-            Some(p) => p,
-            None => alloc::handle_alloc_error(new_layout),
+        self.ptr = match new_ptr {
+            Ok(p) => p.cast(),
+            Err(_) => handle_alloc_error(new_layout),
         };
         self.cap = new_cap;
     }
+
+    // The layout of the current allocation.
+    fn layout(&self) -> Layout {
+        // Can't fail, it was checked when we allocated.
+        Layout::array::<T>(self.cap).unwrap()
+    }
 }
 
 impl<T> Default for RawVec<T> {
@@ -60,25 +140,32 @@ impl<T> Default for RawVec<T> {
     }
 }
 
-impl<T> Drop for RawVec<T> {
+impl<T, A: Allocator> Drop for RawVec<T, A> {
     fn drop(&mut self) {
-        let elem_size = mem::size_of::<T>();
-
-        if self.cap != 0 && elem_size != 0 {
-            let layout = Layout::array::<T>(self.cap).unwrap();
+        if self.cap != 0 && !Self::IS_ZST {
             unsafe {
-                alloc::dealloc(self.ptr.as_ptr() as *mut u8, layout);
+                self.alloc.deallocate(self.ptr.cast(), self.layout());
             }
         }
     }
 }
 
-pub struct Vec<T> {
-    buf: RawVec<T>,
+pub struct Vec<T, A: Allocator = Global> {
+    buf: RawVec<T, A>,
     len: usize,
 }
 
 impl<T> Vec<T> {
+    pub fn new() -> Self {
+        Self::new_in(Global)
+    }
+
+    pub fn with_capacity(cap: usize) -> Self {
+        Self::with_capacity_in(cap, Global)
+    }
+}
+
+impl<T, A: Allocator> Vec<T, A> {
     fn ptr(&self) -> *mut T {
         self.buf.ptr.as_ptr()
     }
@@ -87,13 +174,55 @@ impl<T> Vec<T> {
         self.buf.cap
     }
 
-    pub fn new() -> Self {
+    pub fn new_in(alloc: A) -> Self {
         Vec {
-            buf: RawVec::new(),
+            buf: RawVec::new_in(alloc),
             len: 0,
         }
     }
 
+    pub fn with_capacity_in(cap: usize, alloc: A) -> Self {
+        Vec {
+            buf: RawVec::with_capacity_in(cap, alloc),
+            len: 0,
+        }
+    }
+
+    pub fn allocator(&self) -> &A {
+        self.buf.allocator()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.cap()
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.buf.reserve(self.len, additional);
+    }
+
+    pub fn reserve_exact(&mut self, additional: usize) {
+        self.buf.reserve_exact(self.len, additional);
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.buf.shrink_to(self.len);
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+        let tail = ptr::slice_from_raw_parts_mut(unsafe { self.ptr().add(len) }, self.len - len);
+        // Shorten first, so if a `drop` panics, we don't drop anything twice,
+        // we just leak the rest.
+        self.len = len;
+        unsafe { ptr::drop_in_place(tail) };
+    }
+
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
     pub fn push(&mut self, elem: T) {
         if self.len == self.cap() {
             self.buf.grow();
@@ -150,13 +279,20 @@ impl<T> Vec<T> {
     }
 }
 
+impl<T, A: Allocator> Drop for Vec<T, A> {
+    fn drop(&mut self) {
+        // RawVec frees the memory.
+        self.clear();
+    }
+}
+
 impl<T> Default for Vec<T> {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl<T> Deref for Vec<T> {
+impl<T, A: Allocator> Deref for Vec<T, A> {
     type Target = [T];
 
     fn deref(&self) -> &Self::Target {
@@ -164,13 +300,171 @@ impl<T> Deref for Vec<T> {
     }
 }
 
-impl<T> DerefMut for Vec<T> {
+impl<T, A: Allocator> DerefMut for Vec<T, A> {
     fn deref_mut(&mut self) -> &mut [T] {
         unsafe { std::slice::from_raw_parts_mut(self.ptr(), self.len) }
     }
 }
 
-struct RawValIter<T> {
+impl<T, I: SliceIndex<[T]>, A: Allocator> Index<I> for Vec<T, A> {
+    type Output = I::Output;
+
+    fn index(&self, index: I) -> &Self::Output {
+        Index::index(&**self, index)
+    }
+}
+
+impl<T, I: SliceIndex<[T]>, A: Allocator> IndexMut<I> for Vec<T, A> {
+    fn index_mut(&mut self, index: I) -> &mut Self::Output {
+        IndexMut::index_mut(&mut **self, index)
+    }
+}
+
+impl<T: Clone, A: Allocator + Clone> Clone for Vec<T, A> {
+    fn clone(&self) -> Self {
+        let mut v = Vec::with_capacity_in(self.len, self.allocator().clone());
+        // If a `clone` panics, `v` is dropped along with the clones made so
+        // far, because `push` counts them as they're added.
+        for elem in self.iter() {
+            v.push(elem.clone());
+        }
+        v
+    }
+}
+
+impl<T: fmt::Debug, A: Allocator> fmt::Debug for Vec<T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: PartialEq<U>, U, A1: Allocator, A2: Allocator> PartialEq<Vec<U, A2>> for Vec<T, A1> {
+    fn eq(&self, other: &Vec<U, A2>) -> bool {
+        self[..] == other[..]
+    }
+}
+
+impl<T: PartialEq<U>, U, A: Allocator> PartialEq<[U]> for Vec<T, A> {
+    fn eq(&self, other: &[U]) -> bool {
+        self[..] == other[..]
+    }
+}
+
+impl<T: PartialEq<U>, U, A: Allocator> PartialEq<&[U]> for Vec<T, A> {
+    fn eq(&self, other: &&[U]) -> bool {
+        self[..] == other[..]
+    }
+}
+
+impl<T: PartialEq<U>, U, A: Allocator, const N: usize> PartialEq<[U; N]> for Vec<T, A> {
+    fn eq(&self, other: &[U; N]) -> bool {
+        self[..] == other[..]
+    }
+}
+
+impl<T: PartialEq<U>, U, A: Allocator, const N: usize> PartialEq<&[U; N]> for Vec<T, A> {
+    fn eq(&self, other: &&[U; N]) -> bool {
+        self[..] == other[..]
+    }
+}
+
+impl<T: Eq, A: Allocator> Eq for Vec<T, A> {}
+
+impl<T: PartialOrd, A: Allocator> PartialOrd for Vec<T, A> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self[..].partial_cmp(&other[..])
+    }
+}
+
+impl<T: Ord, A: Allocator> Ord for Vec<T, A> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self[..].cmp(&other[..])
+    }
+}
+
+// Hashes like a slice, so it agrees with `Borrow<[T]>` should we add it.
+impl<T: Hash, A: Allocator> Hash for Vec<T, A> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self[..].hash(state);
+    }
+}
+
+impl<T, A: Allocator> Extend<T> for Vec<T, A> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for elem in iter {
+            self.push(elem);
+        }
+    }
+}
+
+impl<'a, T: Copy + 'a, A: Allocator> Extend<&'a T> for Vec<T, A> {
+    fn extend<I: IntoIterator<Item = &'a T>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().copied());
+    }
+}
+
+impl<T> FromIterator<T> for Vec<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut v = Vec::new();
+        v.extend(iter);
+        v
+    }
+}
+
+impl<T, const N: usize> From<[T; N]> for Vec<T> {
+    fn from(array: [T; N]) -> Self {
+        let array = ManuallyDrop::new(array);
+        let mut v = Vec::with_capacity(N);
+        // SAFETY: We made room for `N` elements, and the array won't drop them.
+        unsafe {
+            ptr::copy_nonoverlapping(array.as_ptr(), v.ptr(), N);
+            v.len = N;
+        }
+        v
+    }
+}
+
+impl<T: Clone> From<&[T]> for Vec<T> {
+    fn from(slice: &[T]) -> Self {
+        slice.iter().cloned().collect()
+    }
+}
+
+// Both allocate with `std::alloc` and a `Layout::array` of their capacity, so
+// the buffer can be handed over as is.
+impl<T> From<std::vec::Vec<T>> for Vec<T> {
+    fn from(v: std::vec::Vec<T>) -> Self {
+        let mut v = ManuallyDrop::new(v);
+        let cap = if RawVec::<T>::IS_ZST {
+            !0
+        } else {
+            v.capacity()
+        };
+        Vec {
+            buf: RawVec {
+                // SAFETY: A `Vec`'s pointer is never null, not even when
+                // it hasn't allocated.
+                ptr: unsafe { NonNull::new_unchecked(v.as_mut_ptr()) },
+                cap,
+                alloc: Global,
+                _marker: PhantomData,
+            },
+            len: v.len(),
+        }
+    }
+}
+
+impl<T> From<Vec<T>> for std::vec::Vec<T> {
+    fn from(v: Vec<T>) -> Self {
+        let v = ManuallyDrop::new(v);
+        // SAFETY: See above, and `v` no longer owns the buffer or elements.
+        unsafe { std::vec::Vec::from_raw_parts(v.ptr(), v.len, v.cap()) }
+    }
+}
+
+pub(super) struct RawValIter<T> {
     start: *const T,
     end: *const T,
 }
@@ -180,7 +474,7 @@ impl<T> RawValIter<T> {
     // This is necessary to store a RawValIter in the same struct as
     // its actual allocation. OK since it's a private implementation
     // detail.
-    unsafe fn new(slice: &[T]) -> Self {
+    pub(super) unsafe fn new(slice: &[T]) -> Self {
         RawValIter {
             start: slice.as_ptr(),
             end: if mem::size_of::<T>() == 0 {
@@ -195,6 +489,18 @@ impl<T> RawValIter<T> {
             },
         }
     }
+
+    // The elements not yielded yet. Only valid while the memory it was made
+    // from is, like the iterator itself.
+    fn as_slice(&self) -> &[T] {
+        let start = if mem::size_of::<T>() == 0 {
+            // `start` only counts for ZSTs, and may not be aligned.
+            NonNull::dangling().as_ptr()
+        } else {
+            self.start
+        };
+        unsafe { slice::from_raw_parts(start, self.len()) }
+    }
 }
 
 impl<T> Iterator for RawValIter<T> {
@@ -241,12 +547,17 @@ impl<T> DoubleEndedIterator for RawValIter<T> {
     }
 }
 
-pub struct IntoIter<T> {
-    _buf: RawVec<T>,
+impl<T> ExactSizeIterator for RawValIter<T> {}
+
+// `start == end` stays true once reached.
+impl<T> FusedIterator for RawValIter<T> {}
+
+pub struct IntoIter<T, A: Allocator = Global> {
+    _buf: RawVec<T, A>,
     iter: RawValIter<T>,
 }
 
-impl<T> Iterator for IntoIter<T> {
+impl<T, A: Allocator> Iterator for IntoIter<T, A> {
     type Item = T;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -258,21 +569,31 @@ impl<T> Iterator for IntoIter<T> {
     }
 }
 
-impl<T> DoubleEndedIterator for IntoIter<T> {
+impl<T, A: Allocator> DoubleEndedIterator for IntoIter<T, A> {
     fn next_back(&mut self) -> Option<T> {
         self.iter.next_back()
     }
 }
 
-impl<T> Drop for IntoIter<T> {
+impl<T, A: Allocator> ExactSizeIterator for IntoIter<T, A> {}
+
+impl<T, A: Allocator> FusedIterator for IntoIter<T, A> {}
+
+impl<T, A: Allocator> IntoIter<T, A> {
+    pub fn as_slice(&self) -> &[T] {
+        self.iter.as_slice()
+    }
+}
+
+impl<T, A: Allocator> Drop for IntoIter<T, A> {
     fn drop(&mut self) {
         for _ in &mut *self {}
     }
 }
 
-impl<T> IntoIterator for Vec<T> {
+impl<T, A: Allocator> IntoIterator for Vec<T, A> {
     type Item = T;
-    type IntoIter = IntoIter<T>;
+    type IntoIter = IntoIter<T, A>;
 
     fn into_iter(self) -> Self::IntoIter {
         unsafe {
@@ -285,12 +606,35 @@ impl<T> IntoIterator for Vec<T> {
     }
 }
 
-pub struct Drain<'a, T: 'a> {
-    vec: PhantomData<&'a mut Vec<T>>,
+impl<'a, T, A: Allocator> IntoIterator for &'a Vec<T, A> {
+    type Item = &'a T;
+    type IntoIter = slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T, A: Allocator> IntoIterator for &'a mut Vec<T, A> {
+    type Item = &'a mut T;
+    type IntoIter = slice::IterMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+pub struct Drain<'a, T: 'a, A: Allocator + 'a = Global> {
+    vec: &'a mut Vec<T, A>,
+    // The elements in the drained range that haven't been yielded yet.
     iter: RawValIter<T>,
+    // The elements after the drained range, which are moved back in place
+    // once the `Drain` is dropped.
+    tail_start: usize,
+    tail_len: usize,
 }
 
-impl<'a, T> Iterator for Drain<'a, T> {
+impl<T, A: Allocator> Iterator for Drain<'_, T, A> {
     type Item = T;
     fn next(&mut self) -> Option<T> {
         self.iter.next()
@@ -300,32 +644,850 @@ impl<'a, T> Iterator for Drain<'a, T> {
     }
 }
 
-impl<'a, T> DoubleEndedIterator for Drain<'a, T> {
+impl<T, A: Allocator> DoubleEndedIterator for Drain<'_, T, A> {
     fn next_back(&mut self) -> Option<T> {
         self.iter.next_back()
     }
 }
 
-impl<'a, T> Drop for Drain<'a, T> {
+impl<T, A: Allocator> ExactSizeIterator for Drain<'_, T, A> {}
+
+impl<T, A: Allocator> FusedIterator for Drain<'_, T, A> {}
+
+impl<T, A: Allocator> Drop for Drain<'_, T, A> {
     fn drop(&mut self) {
-        for _ in &mut *self {}
+        // Moves the tail back even if dropping one of the remaining elements
+        // panics, the rest of those are leaked then.
+        struct MoveTail<'r, 'a, T, A: Allocator>(&'r mut Drain<'a, T, A>);
+
+        impl<T, A: Allocator> Drop for MoveTail<'_, '_, T, A> {
+            fn drop(&mut self) {
+                let drain = &mut *self.0;
+                let start = drain.vec.len;
+                unsafe {
+                    let ptr = drain.vec.ptr();
+                    ptr::copy(ptr.add(drain.tail_start), ptr.add(start), drain.tail_len);
+                }
+                drain.vec.len = start + drain.tail_len;
+            }
+        }
+
+        let guard = MoveTail(self);
+        for _ in &mut guard.0.iter {}
     }
 }
 
-impl<T> Vec<T> {
-    pub fn drain(&mut self) -> Drain<T> {
+// Turns a range of indices into `start..end`, panicking if it's out of bounds.
+pub(super) fn slice_range(range: impl RangeBounds<usize>, len: usize) -> Range<usize> {
+    let start = match range.start_bound() {
+        Bound::Included(&start) => start,
+        Bound::Excluded(&start) => start.checked_add(1).expect("range start overflow"),
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&end) => end.checked_add(1).expect("range end overflow"),
+        Bound::Excluded(&end) => end,
+        Bound::Unbounded => len,
+    };
+    assert!(
+        start <= end,
+        "range starts at {} but ends at {}",
+        start,
+        end
+    );
+    assert!(
+        end <= len,
+        "range end {} out of bounds for length {}",
+        end,
+        len
+    );
+    start..end
+}
+
+impl<T, A: Allocator> Vec<T, A> {
+    pub fn drain(&mut self, range: impl RangeBounds<usize>) -> Drain<'_, T, A> {
+        let Range { start, end } = slice_range(range, self.len);
+        let tail_len = self.len - end;
         unsafe {
-            let iter = RawValIter::new(self);
+            let iter = RawValIter::new(std::slice::from_raw_parts(
+                self.ptr().add(start),
+                end - start,
+            ));
 
             // this is a mem::forget safety thing. If Drain is forgotten, we just
-            // leak the whole Vec's contents. Also we need to do this *eventually*
-            // anyway, so why not do it now?
-            self.len = 0;
+            // leak the drained range and the tail. Also we need to do this
+            // *eventually* anyway, so why not do it now?
+            self.len = start;
 
             Drain {
+                vec: self,
                 iter,
-                vec: PhantomData,
+                tail_start: end,
+                tail_len,
             }
         }
     }
+
+    // Replaces `range` with the items of `replace_with`, and yields what was
+    // there before. Like `drain`, the work is done when the `Splice` is dropped.
+    pub fn splice<I: IntoIterator<Item = T>>(
+        &mut self,
+        range: impl RangeBounds<usize>,
+        replace_with: I,
+    ) -> Splice<'_, I::IntoIter, A> {
+        Splice {
+            drain: self.drain(range),
+            replace_with: replace_with.into_iter(),
+        }
+    }
+
+    pub fn swap_remove(&mut self, index: usize) -> T {
+        assert!(index < self.len, "index out of bounds");
+        unsafe {
+            self.len -= 1;
+            let result = ptr::read(self.ptr().add(index));
+            // Might copy the element onto itself if it was the last one, but
+            // then it's no longer counted in `len`.
+            ptr::copy(self.ptr().add(self.len), self.ptr().add(index), 1);
+            result
+        }
+    }
+
+    pub fn append(&mut self, other: &mut Vec<T, A>) {
+        self.reserve(other.len);
+        unsafe {
+            ptr::copy_nonoverlapping(other.ptr(), self.ptr().add(self.len), other.len);
+        }
+        self.len += other.len;
+        other.len = 0;
+    }
+
+    pub fn retain(&mut self, mut f: impl FnMut(&T) -> bool) {
+        self.retain_mut(|elem| f(elem));
+    }
+
+    pub fn retain_mut(&mut self, mut f: impl FnMut(&mut T) -> bool) {
+        // Compacts the kept elements and puts the unprocessed ones after them
+        // when we're done, or when `f` or a `drop` panics halfway through.
+        struct Guard<'a, T, A: Allocator> {
+            vec: &'a mut Vec<T, A>,
+            len: usize,
+            processed: usize,
+            deleted: usize,
+        }
+
+        impl<T, A: Allocator> Drop for Guard<'_, T, A> {
+            fn drop(&mut self) {
+                unsafe {
+                    let ptr = self.vec.ptr();
+                    ptr::copy(
+                        ptr.add(self.processed),
+                        ptr.add(self.processed - self.deleted),
+                        self.len - self.processed,
+                    );
+                }
+                self.vec.len = self.len - self.deleted;
+            }
+        }
+
+        let len = self.len;
+        // Until the guard is done, the elements are in a state the `Vec`
+        // can't describe, so don't let it see any of them.
+        self.len = 0;
+        let mut g = Guard {
+            vec: self,
+            len,
+            processed: 0,
+            deleted: 0,
+        };
+        while g.processed < len {
+            let cur = unsafe { &mut *g.vec.ptr().add(g.processed) };
+            if !f(cur) {
+                // Count it first, so a panicking `drop` doesn't drop it again.
+                g.processed += 1;
+                g.deleted += 1;
+                unsafe { ptr::drop_in_place(cur) };
+                continue;
+            }
+            if g.deleted > 0 {
+                unsafe {
+                    let hole = g.vec.ptr().add(g.processed - g.deleted);
+                    ptr::copy_nonoverlapping(cur, hole, 1);
+                }
+            }
+            g.processed += 1;
+        }
+    }
+
+    pub fn dedup_by_key<K: PartialEq>(&mut self, mut key: impl FnMut(&mut T) -> K) {
+        self.dedup_by(|a, b| key(a) == key(b));
+    }
+
+    // Removes consecutive elements for which `same_bucket(elem, previous)` is true.
+    pub fn dedup_by(&mut self, mut same_bucket: impl FnMut(&mut T, &mut T) -> bool) {
+        // Elements before `write` are kept, those from `read` on haven't been
+        // looked at. On a panic, the unread ones are moved up to the kept ones.
+        struct Guard<'a, T, A: Allocator> {
+            vec: &'a mut Vec<T, A>,
+            len: usize,
+            read: usize,
+            write: usize,
+        }
+
+        impl<T, A: Allocator> Drop for Guard<'_, T, A> {
+            fn drop(&mut self) {
+                unsafe {
+                    let ptr = self.vec.ptr();
+                    ptr::copy(
+                        ptr.add(self.read),
+                        ptr.add(self.write),
+                        self.len - self.read,
+                    );
+                }
+                self.vec.len = self.write + self.len - self.read;
+            }
+        }
+
+        let len = self.len;
+        if len <= 1 {
+            return;
+        }
+        self.len = 0;
+        let mut g = Guard {
+            vec: self,
+            len,
+            read: 1,
+            write: 1,
+        };
+        while g.read < len {
+            unsafe {
+                let ptr = g.vec.ptr();
+                let read = ptr.add(g.read);
+                let prev = ptr.add(g.write - 1);
+                if same_bucket(&mut *read, &mut *prev) {
+                    g.read += 1;
+                    ptr::drop_in_place(read);
+                } else {
+                    ptr::copy(read, ptr.add(g.write), 1);
+                    g.write += 1;
+                    g.read += 1;
+                }
+            }
+        }
+    }
+
+    // Removes and yields the elements for which `filter` returns true, as the
+    // iterator is advanced. Elements it doesn't get to are kept.
+    pub fn extract_if<F: FnMut(&mut T) -> bool>(&mut self, filter: F) -> ExtractIf<'_, T, F, A> {
+        let len = self.len;
+        // Like in `retain_mut`.
+        self.len = 0;
+        ExtractIf {
+            vec: self,
+            len,
+            idx: 0,
+            deleted: 0,
+            filter,
+        }
+    }
+}
+
+impl<T, A: Allocator + Clone> Vec<T, A> {
+    pub fn split_off(&mut self, at: usize) -> Self {
+        assert!(at <= self.len, "index out of bounds");
+        let mut other = Vec::with_capacity_in(self.len - at, self.allocator().clone());
+        unsafe {
+            ptr::copy_nonoverlapping(self.ptr().add(at), other.ptr(), self.len - at);
+            other.len = self.len - at;
+        }
+        self.len = at;
+        other
+    }
+}
+
+impl<T: PartialEq, A: Allocator> Vec<T, A> {
+    pub fn dedup(&mut self) {
+        self.dedup_by(|a, b| a == b);
+    }
+}
+
+pub struct Splice<'a, I: Iterator + 'a, A: Allocator + 'a = Global> {
+    drain: Drain<'a, I::Item, A>,
+    replace_with: I,
+}
+
+impl<I: Iterator, A: Allocator> Iterator for Splice<'_, I, A> {
+    type Item = I::Item;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.drain.next()
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.drain.size_hint()
+    }
+}
+
+impl<I: Iterator, A: Allocator> DoubleEndedIterator for Splice<'_, I, A> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.drain.next_back()
+    }
+}
+
+impl<I: Iterator, A: Allocator> Drop for Splice<'_, I, A> {
+    fn drop(&mut self) {
+        self.drain.by_ref().for_each(drop);
+        // If `replace_with` panics, the drain still puts the tail back.
+        let mut fill: Vec<I::Item> = self.replace_with.by_ref().collect();
+        let drain = &mut self.drain;
+        let vec = &mut *drain.vec;
+        // Make room for the fill between the drained range and the tail.
+        // Growing reallocates the whole buffer, tail included, even though
+        // it's past `len`.
+        vec.buf.reserve(vec.len + drain.tail_len, fill.len);
+        unsafe {
+            let ptr = vec.ptr();
+            let new_tail = vec.len + fill.len;
+            ptr::copy(ptr.add(drain.tail_start), ptr.add(new_tail), drain.tail_len);
+            drain.tail_start = new_tail;
+            ptr::copy_nonoverlapping(fill.ptr(), ptr.add(vec.len), fill.len);
+            vec.len += fill.len;
+            fill.len = 0;
+        }
+        // Dropping the drain joins the tail, which is already in place.
+    }
+}
+
+pub struct ExtractIf<'a, T, F: FnMut(&mut T) -> bool, A: Allocator = Global> {
+    vec: &'a mut Vec<T, A>,
+    len: usize,
+    // The next element to look at, and how many were extracted before it.
+    idx: usize,
+    deleted: usize,
+    filter: F,
+}
+
+impl<T, F: FnMut(&mut T) -> bool, A: Allocator> Iterator for ExtractIf<'_, T, F, A> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        while self.idx < self.len {
+            unsafe {
+                let ptr = self.vec.ptr();
+                let cur = ptr.add(self.idx);
+                // If `filter` panics, `idx` still points at `cur`, and `Drop`
+                // keeps it.
+                let extract = (self.filter)(&mut *cur);
+                self.idx += 1;
+                if extract {
+                    self.deleted += 1;
+                    return Some(ptr::read(cur));
+                }
+                if self.deleted > 0 {
+                    ptr::copy_nonoverlapping(cur, ptr.add(self.idx - 1 - self.deleted), 1);
+                }
+            }
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.len - self.idx))
+    }
+}
+
+impl<T, F: FnMut(&mut T) -> bool, A: Allocator> Drop for ExtractIf<'_, T, F, A> {
+    fn drop(&mut self) {
+        unsafe {
+            let ptr = self.vec.ptr();
+            ptr::copy(
+                ptr.add(self.idx),
+                ptr.add(self.idx - self.deleted),
+                self.len - self.idx,
+            );
+        }
+        self.vec.len = self.len - self.deleted;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pointers::{
+        alloc::{AllocCounts, Bump, Counting},
+        cell::Cell,
+        test_util::DropCounter,
+    };
+    #[test]
+    fn clone_and_compare() {
+        let v: Vec<_> = vec![String::from("a"), String::from("b")].into();
+        let w = v.clone();
+        assert_eq!(v, w);
+        assert_eq!(v, ["a", "b"]);
+        assert_eq!(v, &["a", "b"][..]);
+        assert_ne!(v, ["a"]);
+        let (small, large) = (Vec::from([1, 2]), Vec::from([1, 3]));
+        assert!(small < large);
+        assert_eq!(format!("{:?}", w), r#"["a", "b"]"#);
+
+        let mut set = std::collections::HashSet::new();
+        set.insert(Vec::from([1, 2]));
+        assert!(set.contains(&Vec::from(&[1, 2][..])));
+    }
+
+    #[test]
+    fn clone_panics_cleanly() {
+        struct Bomb<'a> {
+            clones: &'a Cell<usize>,
+            drops: &'a Cell<usize>,
+        }
+
+        impl Clone for Bomb<'_> {
+            fn clone(&self) -> Self {
+                if self.clones.get() == 2 {
+                    panic!("third clone");
+                }
+                self.clones.set(self.clones.get() + 1);
+                Bomb {
+                    clones: self.clones,
+                    drops: self.drops,
+                }
+            }
+        }
+
+        impl Drop for Bomb<'_> {
+            fn drop(&mut self) {
+                self.drops.set(self.drops.get() + 1);
+            }
+        }
+
+        let (clones, drops) = (Cell::new(0), Cell::new(0));
+        let v: Vec<_> = (0..5)
+            .map(|_| Bomb {
+                clones: &clones,
+                drops: &drops,
+            })
+            .collect();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| v.clone()));
+        assert!(result.is_err());
+        // The two clones that were made are dropped, the originals aren't.
+        assert_eq!((clones.get(), drops.get()), (2, 2));
+        drop(v);
+        assert_eq!(drops.get(), 7);
+    }
+
+    #[test]
+    fn extend_and_collect() {
+        let mut v: Vec<i32> = (1..=3).collect();
+        v.extend(vec![4, 5]);
+        v.extend(&[6, 7]);
+        assert_eq!(v, [1, 2, 3, 4, 5, 6, 7]);
+
+        let drops = Cell::new(0);
+        let v = Vec::from([DropCounter { drops: &drops }, DropCounter { drops: &drops }]);
+        assert_eq!(drops.get(), 0);
+        drop(v);
+        assert_eq!(drops.get(), 2);
+    }
+
+    #[test]
+    fn std_vec_round_trip() {
+        let original = vec![1, 2, 3];
+        let ptr = original.as_ptr();
+        // The buffer is handed over both ways, not copied.
+        let v = Vec::from(original);
+        assert_eq!((v.as_ptr(), v.len(), v.capacity()), (ptr, 3, 3));
+        let back = std::vec::Vec::from(v);
+        assert_eq!(back.as_ptr(), ptr);
+        assert_eq!(back, [1, 2, 3]);
+
+        let units: Vec<()> = vec![(); 5].into();
+        assert_eq!((units.len(), units.capacity()), (5, usize::MAX));
+        assert_eq!(std::vec::Vec::from(units).len(), 5);
+        let empty: Vec<u8> = std::vec::Vec::new().into();
+        assert_eq!(empty.capacity(), 0);
+    }
+
+    #[test]
+    fn borrowing_iterators() {
+        let mut v = Vec::from([1, 2, 3]);
+        for x in &mut v {
+            *x *= 10;
+        }
+        let mut sum = 0;
+        for x in &v {
+            sum += x;
+        }
+        assert_eq!(sum, 60);
+    }
+
+    #[test]
+    fn index_ranges() {
+        let mut v: Vec<_> = (0..10).collect();
+        assert_eq!(v[2..5], [2, 3, 4]);
+        assert_eq!(v[..2], [0, 1]);
+        assert_eq!(v[8..], [8, 9]);
+        assert_eq!(v[..=1], [0, 1]);
+        assert_eq!(v[..].len(), 10);
+        v[..3].fill(7);
+        v[9] = 0;
+        assert_eq!(v, [7, 7, 7, 3, 4, 5, 6, 7, 8, 0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range")]
+    fn index_range_out_of_bounds() {
+        let v = Vec::from([1, 2, 3]);
+        let _ = &v[2..4];
+    }
+
+    #[test]
+    fn exact_size_and_fused() {
+        let mut iter = strings(&["a", "b", "c", "d"]).into_iter();
+        assert_eq!(iter.len(), 4);
+        iter.next();
+        iter.next_back();
+        assert_eq!(iter.as_slice(), ["b", "c"]);
+        assert_eq!(iter.len(), 2);
+        iter.by_ref().for_each(drop);
+        assert_eq!((iter.next(), iter.next_back(), iter.len()), (None, None, 0));
+        assert_eq!(iter.as_slice(), [] as [String; 0]);
+
+        let mut v = strings(&["a", "b", "c", "d"]);
+        let mut drain = v.drain(1..);
+        assert_eq!(drain.len(), 3);
+        drain.next();
+        assert_eq!(drain.len(), 2);
+        drop(drain);
+
+        let mut iter = Vec::from([(), (), ()]).into_iter();
+        iter.next();
+        assert_eq!((iter.len(), iter.as_slice()), (2, &[(), ()][..]));
+    }
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn drain_range() {
+        let mut v = strings(&["a", "b", "c", "d", "e"]);
+        let drained: Vec<_> = v.drain(1..3).collect();
+        assert_eq!(
+            (drained, &v),
+            (strings(&["b", "c"]), &strings(&["a", "d", "e"]))
+        );
+
+        // Dropping a partly consumed drain drops the rest, and joins the tail.
+        let mut drain = v.drain(..=1);
+        assert_eq!(drain.next_back().unwrap(), "d");
+        drop(drain);
+        assert_eq!(v, ["e"]);
+
+        assert_eq!(v.drain(..).collect::<Vec<_>>(), ["e"]);
+        assert!(v.is_empty());
+    }
+
+    #[test]
+    fn forgotten_drain_leaks() {
+        let mut v = strings(&["a", "b", "c", "d"]);
+        std::mem::forget(v.drain(1..2));
+        // Everything from the drained range on is leaked, but never dropped twice.
+        assert_eq!(v, ["a"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn drain_out_of_bounds() {
+        Vec::from([1, 2]).drain(1..3);
+    }
+
+    #[test]
+    fn retain_and_dedup() {
+        let mut v: Vec<i32> = (0..10).collect();
+        v.retain(|&x| x % 3 != 0);
+        assert_eq!(v, [1, 2, 4, 5, 7, 8]);
+        v.retain_mut(|x| {
+            *x *= 10;
+            *x < 60
+        });
+        assert_eq!(v, [10, 20, 40, 50]);
+
+        let mut v = Vec::from([1, 1, 2, 3, 3, 3, 1]);
+        v.dedup();
+        assert_eq!(v, [1, 2, 3, 1]);
+        let mut v = strings(&["a", "A", "b", "B", "b"]);
+        v.dedup_by_key(|s| s.to_lowercase());
+        assert_eq!(v, ["a", "b"]);
+    }
+
+    #[test]
+    fn retain_panics_cleanly() {
+        let drops = Cell::new(0);
+        let mut v: Vec<_> = (0..6).map(|_| DropCounter { drops: &drops }).collect();
+        let mut seen = 0;
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            v.retain(|_| {
+                seen += 1;
+                if seen == 4 {
+                    panic!("halfway through");
+                }
+                seen % 2 == 0
+            })
+        }));
+        assert!(result.is_err());
+        // The first three were looked at, two of which were removed. The one
+        // we panicked on and those after it are kept.
+        assert_eq!((v.len(), drops.get()), (4, 2));
+        drop(v);
+        assert_eq!(drops.get(), 6);
+    }
+
+    #[test]
+    fn dedup_panics_cleanly() {
+        let drops = Cell::new(0);
+        let mut v: Vec<_> = (0..5).map(|_| DropCounter { drops: &drops }).collect();
+        let mut calls = 0;
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            v.dedup_by(|_, _| {
+                calls += 1;
+                if calls == 3 {
+                    panic!("halfway through");
+                }
+                true
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!((v.len(), drops.get()), (3, 2));
+        drop(v);
+        assert_eq!(drops.get(), 5);
+    }
+
+    #[test]
+    fn splice() {
+        let mut v = Vec::from([1, 2, 3, 4, 5]);
+        let removed: Vec<_> = v.splice(1..3, [10, 20, 30, 40]).collect();
+        assert_eq!(removed, [2, 3]);
+        assert_eq!(v, [1, 10, 20, 30, 40, 4, 5]);
+
+        // Fewer items than it replaces, and without consuming the `Splice`.
+        drop(v.splice(1..5, Some(0)));
+        assert_eq!(v, [1, 0, 4, 5]);
+
+        // Needs to grow the buffer while the tail is outside of `len`.
+        let mut v = strings(&["a", "z"]);
+        v.shrink_to_fit();
+        drop(v.splice(1..1, (0..100).map(|i| i.to_string())));
+        assert_eq!((v.len(), &*v[0], &*v[101]), (102, "a", "z"));
+    }
+
+    #[test]
+    fn split_off_append_swap_remove() {
+        let mut v = strings(&["a", "b", "c", "d"]);
+        let mut tail = v.split_off(1);
+        assert_eq!((&v, &tail), (&strings(&["a"]), &strings(&["b", "c", "d"])));
+        assert_eq!(tail.swap_remove(0), "b");
+        assert_eq!(tail, ["d", "c"]);
+        assert_eq!(tail.swap_remove(1), "c");
+        v.append(&mut tail);
+        assert_eq!((&v, tail.len()), (&strings(&["a", "d"]), 0));
+    }
+
+    #[test]
+    fn extract_if() {
+        let mut v: Vec<i32> = (0..10).collect();
+        let evens: Vec<_> = v.extract_if(|x| *x % 2 == 0).collect();
+        assert_eq!(evens, [0, 2, 4, 6, 8]);
+        assert_eq!(v, [1, 3, 5, 7, 9]);
+
+        // Elements after the last one taken are kept if it's dropped early.
+        let mut iter = v.extract_if(|x| *x > 2);
+        assert_eq!(iter.next(), Some(3));
+        drop(iter);
+        assert_eq!(v, [1, 5, 7, 9]);
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            v.extract_if(|x| if *x == 7 { panic!() } else { *x == 5 })
+                .for_each(drop)
+        }));
+        assert!(result.is_err());
+        assert_eq!(v, [1, 7, 9]);
+    }
+
+    #[test]
+    fn with_capacity() {
+        let counting = Counting::new();
+        let mut v = Vec::with_capacity_in(10, &counting);
+        assert_eq!(v.capacity(), 10);
+        for i in 0..10 {
+            v.push(i);
+        }
+        assert_eq!(v.capacity(), 10);
+        drop(v);
+        assert_eq!(
+            counting.counts(),
+            AllocCounts {
+                allocations: 1,
+                deallocations: 1,
+                ..AllocCounts::default()
+            }
+        );
+
+        let counting = Counting::new();
+        drop(Vec::<u8, _>::with_capacity_in(0, &counting));
+        assert_eq!(counting.counts(), AllocCounts::default());
+    }
+
+    #[test]
+    fn reserve() {
+        let counting = Counting::new();
+        let mut v = Vec::new_in(&counting);
+        v.push(1u32);
+        v.reserve(10);
+        assert!(v.capacity() >= 11);
+        // Already enough room.
+        v.reserve(10);
+        v.reserve_exact(10);
+        for i in 0..10 {
+            v.push(i);
+        }
+        assert_eq!(counting.counts().grows, 1);
+
+        v.reserve_exact(5);
+        assert_eq!(v.capacity(), 16);
+        // `reserve` at least doubles.
+        v.reserve(17);
+        assert!(v.capacity() >= 32);
+    }
+
+    #[test]
+    fn pushes_grow_amortized() {
+        let counting = Counting::new();
+        let mut v = Vec::new_in(&counting);
+        for i in 0..1000 {
+            v.push(i);
+        }
+        let counts = counting.counts();
+        assert_eq!(counts.allocations, 1);
+        assert!(counts.grows <= 10, "{:?}", counts);
+    }
+
+    #[test]
+    fn shrink_to_fit() {
+        let counting = Counting::new();
+        let mut v = Vec::with_capacity_in(100, &counting);
+        v.push(1u64);
+        v.push(2);
+        v.shrink_to_fit();
+        assert_eq!(v.capacity(), 2);
+        assert_eq!(*v, [1, 2]);
+        assert_eq!(counting.counts().shrinks, 1);
+
+        v.clear();
+        v.shrink_to_fit();
+        assert_eq!(counting.counts().deallocations, 1);
+        assert_eq!(v.capacity(), 0);
+        v.push(3);
+        assert_eq!(*v, [3]);
+    }
+
+    #[test]
+    fn truncate_and_clear_drop() {
+        let drops = Cell::new(0);
+        let mut v = Vec::new();
+        for _ in 0..5 {
+            v.push(DropCounter { drops: &drops });
+        }
+        v.truncate(10);
+        assert_eq!((v.len(), drops.get()), (5, 0));
+        v.truncate(3);
+        assert_eq!((v.len(), drops.get()), (3, 2));
+        v.clear();
+        assert_eq!((v.len(), drops.get()), (0, 5));
+        assert_eq!(v.capacity(), 8);
+
+        v.push(DropCounter { drops: &drops });
+        drop(v);
+        assert_eq!(drops.get(), 6);
+    }
+
+    #[test]
+    fn zero_sized() {
+        let counting = Counting::new();
+        let mut v = Vec::with_capacity_in(10, &counting);
+        assert_eq!(v.capacity(), usize::MAX);
+        for _ in 0..100 {
+            v.push(());
+        }
+        v.reserve(1000);
+        v.shrink_to_fit();
+        assert_eq!((v.len(), v.capacity()), (100, usize::MAX));
+        drop(v);
+        assert_eq!(counting.counts(), AllocCounts::default());
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity overflow")]
+    fn zero_sized_overflow() {
+        let mut v = Vec::new();
+        v.push(());
+        v.reserve(usize::MAX);
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity overflow")]
+    fn too_large() {
+        Vec::<u64>::with_capacity(usize::MAX / 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity overflow")]
+    fn reserve_overflow() {
+        let mut v = Vec::new();
+        v.push(1u8);
+        v.reserve(usize::MAX);
+    }
+
+    #[test]
+    fn counting_allocator() {
+        let counting = Counting::new();
+        let mut v = Vec::new_in(&counting);
+        for i in 0..100 {
+            v.push(i);
+        }
+        v.shrink_to_fit();
+        let w = v.split_off(50);
+        assert_eq!(w.len(), 50);
+        drop((v, w));
+        assert_eq!(
+            counting.counts(),
+            AllocCounts {
+                allocations: 2,
+                deallocations: 2,
+                // 1 -> 2 -> 4 -> ... -> 128
+                grows: 7,
+                shrinks: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn bump_allocator() {
+        let bump = Bump::with_capacity(1024);
+        let mut v = Vec::with_capacity_in(4, &bump);
+        v.extend(0u32..4);
+        assert_eq!(bump.allocated(), 16);
+        // The last allocation grows and shrinks in place.
+        let ptr = v.as_ptr();
+        v.extend(4..100);
+        assert_eq!((v.as_ptr(), bump.allocated()), (ptr, 4 * v.capacity()));
+        v.truncate(10);
+        v.shrink_to_fit();
+        assert_eq!((v.as_ptr(), bump.allocated()), (ptr, 40));
+
+        // Once something else was allocated after it, growing has to move.
+        let w = Vec::<u32, _>::with_capacity_in(10, &bump);
+        v.reserve(100);
+        assert_ne!(v.as_ptr(), ptr);
+        assert_eq!(v, (0..10).collect::<Vec<_>>());
+        drop((v, w));
+    }
 }
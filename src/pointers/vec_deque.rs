@@ -0,0 +1,651 @@
+use super::vec::{slice_range, RawValIter, RawVec};
+use std::{
+    fmt,
+    iter::{FromIterator, FusedIterator},
+    ops::{Index, IndexMut, Range, RangeBounds},
+    ptr, slice,
+};
+
+// A ring buffer: the elements start at `head` and wrap around the end of the
+// buffer to its start.
+pub struct VecDeque<T> {
+    buf: RawVec<T>,
+    head: usize,
+    len: usize,
+}
+
+impl<T> VecDeque<T> {
+    fn ptr(&self) -> *mut T {
+        self.buf.ptr()
+    }
+
+    fn cap(&self) -> usize {
+        self.buf.capacity()
+    }
+
+    // Where the element at `idx` lives in the buffer, for `idx <= cap`.
+    fn to_physical(&self, idx: usize) -> usize {
+        // Written so it can't overflow, ZSTs have a capacity of `usize::MAX`.
+        let until_end = self.cap() - self.head;
+        if idx >= until_end {
+            idx - until_end
+        } else {
+            self.head + idx
+        }
+    }
+
+    fn is_contiguous(&self) -> bool {
+        self.head <= self.cap() - self.len
+    }
+
+    pub fn new() -> Self {
+        VecDeque {
+            buf: RawVec::new(),
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub fn with_capacity(cap: usize) -> Self {
+        VecDeque {
+            buf: RawVec::with_capacity(cap),
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.cap()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        let old_cap = self.cap();
+        self.buf.reserve(self.len, additional);
+        if self.cap() != old_cap {
+            self.handle_capacity_increase(old_cap);
+        }
+    }
+
+    // Growing keeps the elements where they were in the old buffer, so if
+    // they wrapped around its end, one of the two parts has to move.
+    fn handle_capacity_increase(&mut self, old_cap: usize) {
+        if self.head <= old_cap - self.len {
+            return;
+        }
+        let head_len = old_cap - self.head;
+        let tail_len = self.len - head_len;
+        unsafe {
+            let ptr = self.ptr();
+            if tail_len < head_len && tail_len <= self.cap() - old_cap {
+                // [o o o . . . h h h h] -> [. . . . . . h h h h o o o . .]
+                ptr::copy_nonoverlapping(ptr, ptr.add(old_cap), tail_len);
+            } else {
+                // [o o o o . h h] -> [o o o o . . . . . . . h h]
+                let new_head = self.cap() - head_len;
+                ptr::copy(ptr.add(self.head), ptr.add(new_head), head_len);
+                self.head = new_head;
+            }
+        }
+    }
+
+    pub fn push_back(&mut self, elem: T) {
+        if self.len == self.cap() {
+            self.reserve(1);
+        }
+        unsafe { ptr::write(self.ptr().add(self.to_physical(self.len)), elem) };
+        self.len += 1;
+    }
+
+    pub fn push_front(&mut self, elem: T) {
+        if self.len == self.cap() {
+            self.reserve(1);
+        }
+        self.head = self.to_physical(self.cap() - 1);
+        unsafe { ptr::write(self.ptr().add(self.head), elem) };
+        self.len += 1;
+    }
+
+    pub fn pop_front(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        let old_head = self.head;
+        self.head = self.to_physical(1);
+        self.len -= 1;
+        unsafe { Some(ptr::read(self.ptr().add(old_head))) }
+    }
+
+    pub fn pop_back(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        unsafe { Some(ptr::read(self.ptr().add(self.to_physical(self.len)))) }
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&T> {
+        if idx < self.len {
+            unsafe { Some(&*self.ptr().add(self.to_physical(idx))) }
+        } else {
+            None
+        }
+    }
+
+    pub fn get_mut(&mut self, idx: usize) -> Option<&mut T> {
+        if idx < self.len {
+            unsafe { Some(&mut *self.ptr().add(self.to_physical(idx))) }
+        } else {
+            None
+        }
+    }
+
+    pub fn front(&self) -> Option<&T> {
+        self.get(0)
+    }
+
+    pub fn back(&self) -> Option<&T> {
+        self.len.checked_sub(1).and_then(|idx| self.get(idx))
+    }
+
+    // The elements in order, as the part up to the end of the buffer and the
+    // part that wrapped around to its start.
+    fn slice_ranges(&self) -> (Range<usize>, Range<usize>) {
+        if self.is_contiguous() {
+            (self.head..self.head + self.len, 0..0)
+        } else {
+            let head_len = self.cap() - self.head;
+            (self.head..self.cap(), 0..self.len - head_len)
+        }
+    }
+
+    pub fn as_slices(&self) -> (&[T], &[T]) {
+        let (a, b) = self.slice_ranges();
+        unsafe {
+            (
+                slice::from_raw_parts(self.ptr().add(a.start), a.len()),
+                slice::from_raw_parts(self.ptr().add(b.start), b.len()),
+            )
+        }
+    }
+
+    pub fn as_mut_slices(&mut self) -> (&mut [T], &mut [T]) {
+        let (a, b) = self.slice_ranges();
+        // The ranges don't overlap.
+        unsafe {
+            (
+                slice::from_raw_parts_mut(self.ptr().add(a.start), a.len()),
+                slice::from_raw_parts_mut(self.ptr().add(b.start), b.len()),
+            )
+        }
+    }
+
+    // Moves the elements so they no longer wrap around, and returns them.
+    pub fn make_contiguous(&mut self) -> &mut [T] {
+        if !self.is_contiguous() {
+            // [o o o . . h h h] -> [o o o h h h . .] -> [h h h o o o . .]
+            let head_len = self.cap() - self.head;
+            let tail_len = self.len - head_len;
+            unsafe {
+                let ptr = self.ptr();
+                ptr::copy(ptr.add(self.head), ptr.add(tail_len), head_len);
+                // Only swaps elements, so it can't panic halfway through.
+                slice::from_raw_parts_mut(ptr, self.len).rotate_left(tail_len);
+            }
+            self.head = 0;
+        }
+        unsafe { slice::from_raw_parts_mut(self.ptr().add(self.head), self.len) }
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+        let (front, back) = self.as_mut_slices();
+        let (front, back): (*mut [T], *mut [T]) = if len < front.len() {
+            (&mut front[len..], back)
+        } else {
+            let at = len - front.len();
+            (&mut [], &mut back[at..])
+        };
+        // Like `Vec::truncate`, if a `drop` panics, the rest is leaked.
+        self.len = len;
+        unsafe {
+            ptr::drop_in_place(front);
+            ptr::drop_in_place(back);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.truncate(0);
+        self.head = 0;
+    }
+
+    // Removes `range` and yields what was there. The elements are made
+    // contiguous first, so it works just like `Vec::drain` from there.
+    pub fn drain(&mut self, range: impl RangeBounds<usize>) -> Drain<'_, T> {
+        let Range { start, end } = slice_range(range, self.len);
+        self.make_contiguous();
+        let tail_len = self.len - end;
+        unsafe {
+            let iter = RawValIter::new(slice::from_raw_parts(
+                self.ptr().add(self.head + start),
+                end - start,
+            ));
+            self.len = start;
+            Drain {
+                deque: self,
+                iter,
+                tail_start: end,
+                tail_len,
+            }
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        let (front, back) = self.as_slices();
+        Iter {
+            front: front.iter(),
+            back: back.iter(),
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        let (front, back) = self.as_mut_slices();
+        IterMut {
+            front: front.iter_mut(),
+            back: back.iter_mut(),
+        }
+    }
+}
+
+impl<T> Drop for VecDeque<T> {
+    fn drop(&mut self) {
+        // RawVec frees the memory.
+        self.truncate(0);
+    }
+}
+
+impl<T> Default for VecDeque<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Index<usize> for VecDeque<T> {
+    type Output = T;
+
+    fn index(&self, idx: usize) -> &T {
+        self.get(idx).expect("index out of bounds")
+    }
+}
+
+impl<T> IndexMut<usize> for VecDeque<T> {
+    fn index_mut(&mut self, idx: usize) -> &mut T {
+        self.get_mut(idx).expect("index out of bounds")
+    }
+}
+
+impl<T: Clone> Clone for VecDeque<T> {
+    fn clone(&self) -> Self {
+        self.iter().cloned().collect()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for VecDeque<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T: PartialEq> PartialEq for VecDeque<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl<T: Eq> Eq for VecDeque<T> {}
+
+impl<T> Extend<T> for VecDeque<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for elem in iter {
+            self.push_back(elem);
+        }
+    }
+}
+
+impl<T> FromIterator<T> for VecDeque<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut deque = VecDeque::new();
+        deque.extend(iter);
+        deque
+    }
+}
+
+pub struct Iter<'a, T> {
+    front: slice::Iter<'a, T>,
+    back: slice::Iter<'a, T>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        self.front.next().or_else(|| self.back.next())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.front.len() + self.back.len();
+        (len, Some(len))
+    }
+}
+
+impl<'a, T> DoubleEndedIterator for Iter<'a, T> {
+    fn next_back(&mut self) -> Option<&'a T> {
+        self.back.next_back().or_else(|| self.front.next_back())
+    }
+}
+
+impl<T> ExactSizeIterator for Iter<'_, T> {}
+
+// Slice iterators are fused.
+impl<T> FusedIterator for Iter<'_, T> {}
+
+pub struct IterMut<'a, T> {
+    front: slice::IterMut<'a, T>,
+    back: slice::IterMut<'a, T>,
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = &'a mut T;
+
+    fn next(&mut self) -> Option<&'a mut T> {
+        self.front.next().or_else(|| self.back.next())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.front.len() + self.back.len();
+        (len, Some(len))
+    }
+}
+
+impl<'a, T> DoubleEndedIterator for IterMut<'a, T> {
+    fn next_back(&mut self) -> Option<&'a mut T> {
+        self.back.next_back().or_else(|| self.front.next_back())
+    }
+}
+
+impl<T> ExactSizeIterator for IterMut<'_, T> {}
+
+impl<T> FusedIterator for IterMut<'_, T> {}
+
+pub struct IntoIter<T> {
+    deque: VecDeque<T>,
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.deque.pop_front()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.deque.len, Some(self.deque.len))
+    }
+}
+
+impl<T> DoubleEndedIterator for IntoIter<T> {
+    fn next_back(&mut self) -> Option<T> {
+        self.deque.pop_back()
+    }
+}
+
+impl<T> ExactSizeIterator for IntoIter<T> {}
+
+// An empty deque stays empty.
+impl<T> FusedIterator for IntoIter<T> {}
+
+impl<T> IntoIterator for VecDeque<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter { deque: self }
+    }
+}
+
+impl<'a, T> IntoIterator for &'a VecDeque<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut VecDeque<T> {
+    type Item = &'a mut T;
+    type IntoIter = IterMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+pub struct Drain<'a, T: 'a> {
+    deque: &'a mut VecDeque<T>,
+    iter: RawValIter<T>,
+    tail_start: usize,
+    tail_len: usize,
+}
+
+impl<T> Iterator for Drain<'_, T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.iter.next()
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<T> DoubleEndedIterator for Drain<'_, T> {
+    fn next_back(&mut self) -> Option<T> {
+        self.iter.next_back()
+    }
+}
+
+impl<T> ExactSizeIterator for Drain<'_, T> {}
+
+impl<T> FusedIterator for Drain<'_, T> {}
+
+impl<T> Drop for Drain<'_, T> {
+    fn drop(&mut self) {
+        // Like `vec::Drain`, the tail is moved back even if a `drop` panics.
+        struct MoveTail<'r, 'a, T>(&'r mut Drain<'a, T>);
+
+        impl<T> Drop for MoveTail<'_, '_, T> {
+            fn drop(&mut self) {
+                let drain = &mut *self.0;
+                let deque = &mut *drain.deque;
+                unsafe {
+                    let ptr = deque.ptr().add(deque.head);
+                    ptr::copy(
+                        ptr.add(drain.tail_start),
+                        ptr.add(deque.len),
+                        drain.tail_len,
+                    );
+                }
+                deque.len += drain.tail_len;
+            }
+        }
+
+        let guard = MoveTail(self);
+        for _ in &mut guard.0.iter {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pointers::{cell::Cell, test_util::DropCounter};
+
+    // A deque of capacity 8 whose elements 0..len start at `head`.
+    fn wrapped(head: usize, len: usize) -> VecDeque<usize> {
+        let mut d = VecDeque::with_capacity(8);
+        for _ in 0..head {
+            d.push_back(0);
+            d.pop_front();
+        }
+        d.extend(0..len);
+        assert_eq!((d.capacity(), d.head), (8, head));
+        d
+    }
+
+    #[test]
+    fn both_ends() {
+        let mut d = VecDeque::new();
+        d.push_back(1);
+        d.push_front(0);
+        d.push_back(2);
+        assert_eq!((d.front(), d.back()), (Some(&0), Some(&2)));
+        assert_eq!(d.pop_back(), Some(2));
+        assert_eq!(d.pop_front(), Some(0));
+        assert_eq!(d.pop_front(), Some(1));
+        assert_eq!((d.pop_front(), d.pop_back()), (None, None));
+
+        for i in 0..100 {
+            if i % 2 == 0 {
+                d.push_back(i);
+            } else {
+                d.push_front(i);
+            }
+        }
+        let expected = (0..100).rev().filter(|i| i % 2 == 1);
+        let expected = expected.chain((0..100).filter(|i| i % 2 == 0));
+        assert!(d.iter().copied().eq(expected.clone()));
+        assert!(d.into_iter().rev().eq(expected.rev()));
+    }
+
+    #[test]
+    fn indexing() {
+        let mut d = wrapped(6, 5);
+        assert_eq!(d.as_slices(), (&[0, 1][..], &[2, 3, 4][..]));
+        d[3] = 30;
+        assert_eq!(d[3], 30);
+        assert_eq!(d.get(5), None);
+        for x in &mut d {
+            *x += 1;
+        }
+        assert_eq!(d.iter().copied().collect::<Vec<_>>(), [1, 2, 3, 31, 5]);
+    }
+
+    #[test]
+    #[should_panic(expected = "index out of bounds")]
+    fn index_out_of_bounds() {
+        let _ = &wrapped(6, 5)[5];
+    }
+
+    #[test]
+    fn iterator_lengths() {
+        let mut d = wrapped(6, 5);
+        let mut iter = d.iter();
+        assert_eq!(iter.len(), 5);
+        iter.next_back();
+        assert_eq!(iter.len(), 4);
+        assert_eq!(d.iter_mut().skip(3).len(), 2);
+        assert_eq!(d.drain(1..3).len(), 2);
+        let mut iter = d.into_iter();
+        iter.next();
+        assert_eq!(iter.len(), 2);
+        assert_eq!(iter.by_ref().count(), 2);
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn grows_while_wrapped() {
+        // The short wrapped part moves after the old end.
+        let mut d = wrapped(3, 8);
+        d.push_back(8);
+        assert_eq!(d.capacity(), 16);
+        assert_eq!(d.as_slices(), (&[0, 1, 2, 3, 4, 5, 6, 7, 8][..], &[][..]));
+
+        // The short front part moves to the new end.
+        let mut d = wrapped(7, 8);
+        d.push_front(100);
+        assert_eq!(d.head, 14);
+        assert_eq!(d.as_slices().0, [100, 0]);
+        assert!(d.iter().copied().eq(Some(100).into_iter().chain(0..8)));
+    }
+
+    #[test]
+    fn make_contiguous() {
+        for head in 0..8 {
+            for len in 0..=8 {
+                let mut d = wrapped(head, len);
+                assert!(d.make_contiguous().iter().copied().eq(0..len));
+                assert_eq!(d.as_slices().1, []);
+                assert!(d.iter().copied().eq(0..len));
+            }
+        }
+    }
+
+    #[test]
+    fn drain() {
+        let mut d = wrapped(5, 7);
+        let drained = d.drain(2..5).collect::<Vec<_>>();
+        assert_eq!(drained, [2, 3, 4]);
+        assert!(d.iter().copied().eq([0, 1, 5, 6]));
+
+        let mut d = wrapped(5, 7);
+        let mut drain = d.drain(1..);
+        assert_eq!(drain.next_back(), Some(6));
+        drop(drain);
+        assert!(d.iter().copied().eq([0]));
+        d.push_front(9);
+        assert!(d.iter().copied().eq([9, 0]));
+    }
+
+    #[test]
+    fn drops_elements() {
+        let drops = Cell::new(0);
+        let mut d = VecDeque::with_capacity(4);
+        d.push_back(DropCounter { drops: &drops });
+        d.pop_front();
+        assert_eq!(drops.get(), 1);
+        for _ in 0..4 {
+            d.push_back(DropCounter { drops: &drops });
+        }
+        d.truncate(3);
+        assert_eq!(drops.get(), 2);
+        drop(d.drain(..1));
+        assert_eq!(drops.get(), 3);
+        drop(d);
+        assert_eq!(drops.get(), 5);
+    }
+
+    #[test]
+    fn zero_sized() {
+        let mut d = VecDeque::new();
+        for _ in 0..10 {
+            d.push_front(());
+            d.push_back(());
+        }
+        assert_eq!((d.len(), d.capacity()), (20, usize::MAX));
+        assert_eq!(d.iter().count(), 20);
+        assert_eq!(d.make_contiguous().len(), 20);
+        assert_eq!(d.drain(5..10).count(), 5);
+        assert_eq!(d.pop_front(), Some(()));
+        assert_eq!(d.into_iter().count(), 14);
+    }
+}
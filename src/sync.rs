@@ -0,0 +1,58 @@
+// The synchronization primitives used by the modules that can be checked with
+// loom. A normal build uses std's, a build with `--cfg loom` uses loom's, which
+// run the tests in `loom::model` once for every possible interleaving and
+// every outcome the memory orderings allow:
+//
+//     RUSTFLAGS="--cfg loom" cargo test --release loom
+//
+// Only the loom tests can run in such a build, loom's types panic outside of
+// `loom::model`.
+
+#[cfg(not(loom))]
+pub(crate) use std::{
+    hint,
+    sync::{atomic, Arc, Condvar, Mutex},
+    thread,
+};
+
+#[cfg(loom)]
+pub(crate) use loom::{
+    hint,
+    sync::{atomic, Arc, Condvar, Mutex},
+};
+
+#[cfg(loom)]
+pub(crate) mod thread {
+    pub(crate) use loom::thread::*;
+    // Loom has no notion of time, sleeping threads just run later.
+    pub(crate) use std::thread::sleep;
+}
+
+#[cfg(loom)]
+pub(crate) use loom::cell::UnsafeCell;
+
+// Loom can only check accesses to an `UnsafeCell` if it knows where they start
+// and end, so its `UnsafeCell` hands out raw pointers to closures instead of
+// having a `get`. This is the same API over std's.
+#[cfg(not(loom))]
+#[derive(Debug)]
+pub(crate) struct UnsafeCell<T>(std::cell::UnsafeCell<T>);
+
+#[cfg(not(loom))]
+impl<T> UnsafeCell<T> {
+    pub(crate) fn new(data: T) -> Self {
+        UnsafeCell(std::cell::UnsafeCell::new(data))
+    }
+
+    pub(crate) fn with<R>(&self, f: impl FnOnce(*const T) -> R) -> R {
+        f(self.0.get())
+    }
+
+    pub(crate) fn with_mut<R>(&self, f: impl FnOnce(*mut T) -> R) -> R {
+        f(self.0.get())
+    }
+
+    pub(crate) fn into_inner(self) -> T {
+        self.0.into_inner()
+    }
+}
@@ -117,4 +117,26 @@ mod tests {
 
     //     bar(&"a");
     // }
+
+    #[test]
+    fn rc_trait_object() {
+        use super::Hei;
+        use crate::pointers::rc::Rc;
+
+        // `Hei` itself isn't object safe because of `weird`, so use `AsRef<str>`.
+        // `Rc<dyn AsRef<str>>` is a fat pointer just like `&dyn AsRef<str>`: a
+        // pointer to the RcInner holding the instance, and a pointer to the vtable.
+        assert_eq!(
+            std::mem::size_of::<Rc<dyn AsRef<str>>>(),
+            2 * std::mem::size_of::<usize>()
+        );
+
+        let hs: [Rc<dyn AsRef<str>>; 2] = [
+            crate::unsize_rc!(Rc::new("a"), dyn AsRef<str>),
+            Rc::from(Box::new(String::from("b")) as Box<dyn AsRef<str>>),
+        ];
+        for h in &hs {
+            (**h).as_ref().hei();
+        }
+    }
 }